@@ -0,0 +1,79 @@
+//! Compare the resizing and decoding paths on a large photo, run with `cargo bench`
+use image::codecs::jpeg::JpegEncoder;
+use image::imageops::FilterType;
+use image::io::Limits;
+use image::{DynamicImage, ImageFormat, RgbImage};
+use pic::decode::{decode_buffer, decode_buffer_scaled};
+use pic::resize::{resize, Filter};
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+/// A 48 megapixels photo
+const SIZE: (u32, u32) = (8000, 6000);
+/// Unicode blocks of a full terminal, and the pixels of its cells
+const TARGETS: [(u32, u32); 2] = [(160, 120), (1600, 1200)];
+const RUNS: u32 = 3;
+
+/// Best time out of a few runs
+fn bench(name: &str, mut run: impl FnMut() -> DynamicImage) {
+    let mut best = Duration::MAX;
+    for _ in 0..RUNS {
+        let start = Instant::now();
+        black_box(run());
+        best = best.min(start.elapsed());
+    }
+    println!("{name:<48} {best:>12.2?}");
+}
+
+/// Smooth gradients with some noise, which is what photos look like to resamplers
+fn photo() -> DynamicImage {
+    let (width, height) = SIZE;
+    DynamicImage::ImageRgb8(RgbImage::from_fn(width, height, |x, y| {
+        let noise = (x.wrapping_mul(7919) ^ y.wrapping_mul(104_729)) % 32;
+        image::Rgb([
+            (x * 255 / width) as u8 ^ noise as u8,
+            (y * 255 / height) as u8,
+            ((x + y) % 256) as u8,
+        ])
+    }))
+}
+
+fn main() {
+    let image = photo();
+    let mut jpeg = Vec::new();
+    JpegEncoder::new_with_quality(&mut jpeg, 90)
+        .encode_image(&image)
+        .expect("photo can be encoded");
+
+    for (width, height) in TARGETS {
+        println!("{}x{} to {width}x{height}", SIZE.0, SIZE.1);
+        bench("resize_exact (image, triangle)", || {
+            image.resize_exact(width, height, FilterType::Triangle)
+        });
+        bench("resize (triangle)", || {
+            resize(&image, width, height, Filter::Triangle, false)
+        });
+        bench("resize (lanczos3)", || {
+            resize(&image, width, height, Filter::Lanczos3, false)
+        });
+        bench("resize (box, linear)", || {
+            resize(&image, width, height, Filter::Box, true)
+        });
+        bench("decode + resize_exact (image, triangle)", || {
+            decode_buffer(&jpeg, ImageFormat::Jpeg, Limits::no_limits())
+                .expect("photo can be decoded")
+                .resize_exact(width, height, FilterType::Triangle)
+        });
+        bench("decode scaled + resize (triangle)", || {
+            let image = decode_buffer_scaled(
+                &jpeg,
+                ImageFormat::Jpeg,
+                (width, height),
+                Limits::no_limits(),
+            )
+            .expect("photo can be decoded");
+            resize(&image, width, height, Filter::Triangle, false)
+        });
+        println!();
+    }
+}
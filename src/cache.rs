@@ -0,0 +1,276 @@
+use crate::decode::LoadedImage;
+use crate::layout::fit_mode;
+use crate::options::Options;
+use crate::result::Result;
+use crate::utils::TermSize;
+use std::env;
+use std::fs::{self, File};
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::io::Write;
+use std::path::PathBuf;
+use std::time::SystemTime;
+
+/// Size the cache is kept under when not given, in MiB
+const DEFAULT_SIZE: u64 = 100;
+
+/// Base directory of user caches, `$XDG_CACHE_HOME` or `~/.cache`
+pub fn cache_home() -> Option<PathBuf> {
+    match env::var_os("XDG_CACHE_HOME") {
+        Some(dir) if !dir.is_empty() => Some(PathBuf::from(dir)),
+        _ => Some(PathBuf::from(env::var_os("HOME")?).join(".cache")),
+    }
+}
+
+/// Hash the options a render depends on
+fn hash_options(options: &Options, hasher: &mut impl Hasher) -> Result {
+    let term_size = TermSize::from_options(options)?;
+    // Where the preview goes and how large it is
+    (term_size.cols, term_size.rows).hash(hasher);
+    term_size.cell_size().hash(hasher);
+    (options.x, options.y, options.cols, options.rows).hash(hasher);
+    (fit_mode(options), options.scale, options.upscale).hash(hasher);
+    // Which part of the image is shown, and how its pixels are computed
+    (options.crop, options.rotate, options.flip).hash(hasher);
+    options
+        .zoom
+        .map(|zoom| {
+            (
+                zoom.factor.to_bits(),
+                zoom.center.0.to_bits(),
+                zoom.center.1.to_bits(),
+            )
+        })
+        .hash(hasher);
+    (options.filter, options.linear, options.thumbnails).hash(hasher);
+    (options.background, options.alpha_threshold).hash(hasher);
+    (options.gif_static, options.no_newline).hash(hasher);
+    Ok(())
+}
+
+/// Renders of previews kept on disk, so that showing the same image again
+/// doesn't need decoding and resizing it
+pub struct Cache {
+    dir: PathBuf,
+    max_size: u64,
+}
+
+impl Cache {
+    /// Directory of the cache, `$XDG_CACHE_HOME/pic` or `~/.cache/pic`
+    pub fn dir() -> Option<PathBuf> {
+        Some(cache_home()?.join("pic"))
+    }
+
+    /// Cache to use with the given options, `None` if it is disabled
+    pub fn open(options: &Options) -> Option<Self> {
+        if options.no_cache {
+            return None;
+        }
+
+        Some(Self {
+            dir: Cache::dir()?,
+            max_size: options.cache_size.unwrap_or(DEFAULT_SIZE) * 1024 * 1024,
+        })
+    }
+
+    /// Remove all the renders from the cache
+    pub fn clear() -> Result {
+        match Cache::dir() {
+            Some(dir) if dir.is_dir() => Ok(fs::remove_dir_all(dir)?),
+            _ => Ok(()),
+        }
+    }
+
+    /// Key of a render, from the identity of the image file (path, modification
+    /// time and size) and the options the render depends on
+    ///
+    /// Keys only need to be stable for a given build, which `DefaultHasher` is
+    pub fn key(image: &LoadedImage, kind: &str, options: &Options) -> Result<String> {
+        let metadata = fs::metadata(image.path())?;
+
+        let mut hasher = DefaultHasher::new();
+        env!("CARGO_PKG_VERSION").hash(&mut hasher);
+        fs::canonicalize(image.path())?.hash(&mut hasher);
+        metadata.modified()?.hash(&mut hasher);
+        metadata.len().hash(&mut hasher);
+        kind.hash(&mut hasher);
+        hash_options(options, &mut hasher)?;
+        Ok(format!("{:016x}", hasher.finish()))
+    }
+
+    /// Key of a render from the content of the image file instead of its identity,
+    /// the same for copies of the file
+    pub fn content_key(image: &LoadedImage, kind: &str, options: &Options) -> Result<String> {
+        let mut hasher = DefaultHasher::new();
+        env!("CARGO_PKG_VERSION").hash(&mut hasher);
+        image.buffer()?.hash(&mut hasher);
+        kind.hash(&mut hasher);
+        hash_options(options, &mut hasher)?;
+        Ok(format!("{:016x}", hasher.finish()))
+    }
+
+    /// Cached render, if any
+    pub fn get(&self, key: &str) -> Option<Vec<u8>> {
+        let path = self.dir.join(key);
+        let data = fs::read(&path).ok()?;
+        // Renders used recently are evicted last
+        let _ = File::options()
+            .write(true)
+            .open(&path)
+            .and_then(|file| file.set_modified(SystemTime::now()));
+        Some(data)
+    }
+
+    /// Store a render, evicting the least recently used ones if the cache gets too large
+    pub fn put(&self, key: &str, data: &[u8]) -> Result {
+        fs::create_dir_all(&self.dir)?;
+        if data.len() as u64 <= self.max_size {
+            // Renders are written whole or not at all, even with concurrent previews
+            let mut file = tempfile::Builder::new()
+                .prefix(".")
+                .tempfile_in(&self.dir)?;
+            file.write_all(data)?;
+            file.persist(self.dir.join(key))?;
+        }
+        self.evict()
+    }
+
+    fn evict(&self) -> Result {
+        let mut renders: Vec<(SystemTime, u64, PathBuf)> = fs::read_dir(&self.dir)?
+            .filter_map(|entry| {
+                let entry = entry.ok()?;
+                // Skip renders still being written
+                if entry.file_name().to_string_lossy().starts_with('.') {
+                    return None;
+                }
+                let metadata = entry.metadata().ok()?;
+                Some((metadata.modified().ok()?, metadata.len(), entry.path()))
+            })
+            .collect();
+        renders.sort();
+
+        let mut size: u64 = renders.iter().map(|(_, len, _)| len).sum();
+        for (_, len, path) in renders {
+            if size <= self.max_size {
+                break;
+            }
+            if fs::remove_file(path).is_ok() {
+                size -= len;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Write the cached render of an image, or render it, then write and cache it
+///
+/// The cache only makes previews faster, failing to use it doesn't fail them
+pub fn write_cached(
+    stdout: &mut impl Write,
+    image: &LoadedImage,
+    kind: &str,
+    options: &Options,
+    render: impl FnOnce(&mut Vec<u8>) -> Result,
+) -> Result {
+    let cache = Cache::open(options).and_then(|cache| {
+        let key = Cache::key(image, kind, options).ok()?;
+        Some((cache, key))
+    });
+
+    let data = match cache.as_ref().and_then(|(cache, key)| cache.get(key)) {
+        Some(data) => data,
+        None => {
+            let mut data = Vec::new();
+            render(&mut data)?;
+            if let Some((cache, key)) = &cache {
+                let _ = cache.put(key, &data);
+            }
+            data
+        }
+    };
+
+    stdout.write_all(&data)?;
+    stdout.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn options() -> Options {
+        let mut options = Options::new(Vec::new());
+        options.term_cols = Some(80);
+        options.term_rows = Some(24);
+        options
+    }
+
+    #[test]
+    fn keys_change_with_the_options() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("image.png");
+        fs::write(&path, b"pixels").unwrap();
+        let image = LoadedImage::new(&path);
+        let key = |kind, options: &Options| Cache::key(&image, kind, options).unwrap();
+
+        let default = key("blocks", &options());
+        assert_eq!(key("blocks", &options()), default);
+        assert_ne!(key("kitty", &options()), default);
+
+        let mut upscaled = options();
+        upscaled.upscale = true;
+        assert_ne!(key("blocks", &upscaled), default);
+        let mut smaller = options();
+        smaller.term_cols = Some(40);
+        assert_ne!(key("blocks", &smaller), default);
+
+        // Options of the cache itself don't change renders
+        let mut cached = options();
+        cached.cache_size = Some(1);
+        assert_eq!(key("blocks", &cached), default);
+
+        // Copies of a file only share content keys
+        let copy = dir.path().join("copy.png");
+        fs::copy(&path, &copy).unwrap();
+        let copy = LoadedImage::new(&copy);
+        assert_ne!(Cache::key(&copy, "blocks", &options()).unwrap(), default);
+        assert_eq!(
+            Cache::content_key(&copy, "blocks", &options()).unwrap(),
+            Cache::content_key(&image, "blocks", &options()).unwrap()
+        );
+    }
+
+    #[test]
+    fn least_recently_used_renders_are_evicted_first() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = Cache {
+            dir: dir.path().to_path_buf(),
+            max_size: 10,
+        };
+        let age = |key: &str, seconds| {
+            File::options()
+                .write(true)
+                .open(dir.path().join(key))
+                .and_then(|file| {
+                    file.set_modified(SystemTime::now() - Duration::from_secs(seconds))
+                })
+                .unwrap()
+        };
+
+        cache.put("first", b"1234").unwrap();
+        cache.put("second", b"5678").unwrap();
+        age("first", 20);
+        age("second", 10);
+        // Reading a render makes it the most recently used
+        assert_eq!(cache.get("first").as_deref(), Some(&b"1234"[..]));
+
+        cache.put("third", b"9012").unwrap();
+        assert_eq!(cache.get("second"), None);
+        assert!(cache.get("first").is_some());
+        assert!(cache.get("third").is_some());
+
+        // Renders larger than the whole cache aren't kept
+        cache.put("large", &[0; 11]).unwrap();
+        assert_eq!(cache.get("large"), None);
+    }
+}
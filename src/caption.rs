@@ -0,0 +1,150 @@
+use crate::decode::LoadedImage;
+use crate::result::Result;
+use clap::ValueEnum;
+use serde::Deserialize;
+use std::path::Path;
+
+/// Where captions go relative to the previews
+#[derive(Copy, Clone, Default, PartialEq, Eq, PartialOrd, Ord, ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CaptionPosition {
+    Above,
+    #[default]
+    Below,
+}
+
+/// Expand a caption template for the given image
+///
+/// `name`, `dimensions`, `format`, `size` and `full` are shortcuts, otherwise
+/// `{name}`, `{path}`, `{width}`, `{height}`, `{format}` and `{size}` are replaced
+pub fn caption(image: &LoadedImage, template: &str) -> Result<String> {
+    let image_path = image.path();
+    let template = match template {
+        "name" => "{name}",
+        "dimensions" => "{width}x{height}",
+        "format" => "{format}",
+        "size" => "{size}",
+        "full" => "{name} {width}x{height} {format} {size}",
+        template => template,
+    };
+
+    let mut caption = template
+        .replace("{name}", &file_name(image_path))
+        .replace("{path}", &image_path.display().to_string());
+    if caption.contains("{width}") || caption.contains("{height}") {
+        let (width, height) = image.size()?;
+        caption = caption
+            .replace("{width}", &width.to_string())
+            .replace("{height}", &height.to_string());
+    }
+    if caption.contains("{format}") {
+        caption = caption.replace("{format}", &format(image)?);
+    }
+    if caption.contains("{size}") {
+        let size = std::fs::metadata(image_path)?.len();
+        caption = caption.replace("{size}", &human_size(size));
+    }
+
+    Ok(caption)
+}
+
+/// Cut text to the given number of cols, with an ellipsis if needed
+pub fn truncate(text: &str, cols: u32) -> String {
+    let cols = cols as usize;
+    if text.chars().count() <= cols {
+        text.to_string()
+    } else if cols == 0 {
+        String::new()
+    } else {
+        let mut text: String = text.chars().take(cols - 1).collect();
+        text.push('…');
+        text
+    }
+}
+
+fn file_name(image_path: &Path) -> String {
+    image_path
+        .file_name()
+        .unwrap_or(image_path.as_os_str())
+        .to_string_lossy()
+        .into_owned()
+}
+
+fn format(image: &LoadedImage) -> Result<String> {
+    #[cfg(feature = "svg")]
+    if image.is_svg()? {
+        return Ok(String::from("SVG"));
+    }
+
+    Ok(image.format()?.map_or(String::from("?"), |format| {
+        format!("{format:?}").to_uppercase()
+    }))
+}
+
+fn human_size(size: u64) -> String {
+    const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+    if size < 1024 {
+        return format!("{size} B");
+    }
+
+    let mut size = size as f64 / 1024.0;
+    let mut unit = 0;
+    // Sizes that would round up to 1024.0 are shown in the next unit
+    while size >= 1023.95 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    format!("{size:.1} {}", UNITS[unit])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::RgbImage;
+
+    #[test]
+    fn text_is_truncated_by_chars() {
+        assert_eq!(truncate("image.png", 9), "image.png");
+        assert_eq!(truncate("image.png", 6), "image…");
+        assert_eq!(truncate("été à Noël.jpg", 5), "été …");
+        assert_eq!(truncate("image.png", 1), "…");
+        assert_eq!(truncate("image.png", 0), "");
+    }
+
+    #[test]
+    fn sizes_use_the_largest_unit_below_1024() {
+        assert_eq!(human_size(0), "0 B");
+        assert_eq!(human_size(1023), "1023 B");
+        assert_eq!(human_size(1024), "1.0 KiB");
+        assert_eq!(human_size(1536), "1.5 KiB");
+        assert_eq!(human_size(1024 * 1024 - 1), "1.0 MiB");
+        assert_eq!(human_size(1024 * 1024), "1.0 MiB");
+        assert_eq!(human_size(5 * 1024 * 1024 * 1024), "5.0 GiB");
+        assert_eq!(human_size(2048 * 1024 * 1024 * 1024 * 1024), "2048.0 TiB");
+    }
+
+    #[test]
+    fn templates_are_expanded() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("image.png");
+        RgbImage::new(3, 2).save(&path).unwrap();
+        let image = LoadedImage::new(&path);
+
+        assert_eq!(caption(&image, "name").unwrap(), "image.png");
+        assert_eq!(caption(&image, "dimensions").unwrap(), "3x2");
+        assert_eq!(caption(&image, "format").unwrap(), "PNG");
+        assert_eq!(
+            caption(&image, "{name} ({width}x{height})").unwrap(),
+            "image.png (3x2)"
+        );
+        assert_eq!(
+            caption(&image, "{path}").unwrap(),
+            path.display().to_string()
+        );
+        // Unknown placeholders are kept as they are
+        assert_eq!(
+            caption(&image, "{name} {date} {}").unwrap(),
+            "image.png {date} {}"
+        );
+    }
+}
@@ -0,0 +1,256 @@
+use crate::options::Options;
+use crate::paths::Sort;
+use crate::result::Result;
+use crate::support::{Background, ColorMode, Protocol};
+use crate::thumbnail::ThumbnailMode;
+use clap::{parser::ValueSource, ArgMatches};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::{env, fs, path::PathBuf};
+
+/// Defaults that can be set in the config file, globally or per terminal
+#[derive(Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Defaults {
+    pub protocol: Option<Protocol>,
+    pub cols: Option<u32>,
+    pub rows: Option<u32>,
+    pub spacing: Option<u32>,
+    pub upscale: Option<bool>,
+    #[serde(rename = "static")]
+    pub gif_static: Option<bool>,
+    #[serde(rename = "loop")]
+    pub gif_loop: Option<bool>,
+    pub colors: Option<ColorMode>,
+    pub sort: Option<Sort>,
+    pub background: Option<Background>,
+    #[serde(rename = "alpha-threshold")]
+    pub alpha_threshold: Option<u8>,
+    #[serde(rename = "max-dimension")]
+    pub max_dimension: Option<u32>,
+    #[serde(rename = "max-memory")]
+    pub max_memory: Option<u64>,
+    #[serde(rename = "cache-size")]
+    pub cache_size: Option<u64>,
+    pub thumbnails: Option<ThumbnailMode>,
+}
+
+impl Defaults {
+    /// Fill values that are still unset with the ones from other
+    fn or(self, other: &Defaults) -> Self {
+        Self {
+            protocol: self.protocol.or(other.protocol),
+            cols: self.cols.or(other.cols),
+            rows: self.rows.or(other.rows),
+            spacing: self.spacing.or(other.spacing),
+            upscale: self.upscale.or(other.upscale),
+            gif_static: self.gif_static.or(other.gif_static),
+            gif_loop: self.gif_loop.or(other.gif_loop),
+            colors: self.colors.or(other.colors),
+            sort: self.sort.or(other.sort),
+            background: self.background.or(other.background),
+            alpha_threshold: self.alpha_threshold.or(other.alpha_threshold),
+            max_dimension: self.max_dimension.or(other.max_dimension),
+            max_memory: self.max_memory.or(other.max_memory),
+            cache_size: self.cache_size.or(other.cache_size),
+            thumbnails: self.thumbnails.or(other.thumbnails),
+        }
+    }
+}
+
+/// Content of the config file
+///
+/// Precedence is: CLI flags, then `PIC_*` environment variables,
+/// then `[terminal.<TERM_PROGRAM>]`, then `[terminal.<TERM>]`, then top-level values
+///
+/// Unknown keys are rejected, serde can't when top-level values are flattened
+#[derive(Clone, Default, Deserialize)]
+#[serde(try_from = "toml::Table")]
+pub struct Config {
+    pub defaults: Defaults,
+    /// Overrides keyed by TERM or TERM_PROGRAM
+    pub terminal: HashMap<String, Defaults>,
+}
+
+impl TryFrom<toml::Table> for Config {
+    type Error = toml::de::Error;
+
+    fn try_from(mut table: toml::Table) -> std::result::Result<Self, Self::Error> {
+        let terminal = match table.remove("terminal") {
+            Some(terminal) => terminal.try_into()?,
+            None => HashMap::new(),
+        };
+        Ok(Self {
+            defaults: toml::Value::Table(table).try_into()?,
+            terminal,
+        })
+    }
+}
+
+impl Config {
+    /// Path of the config file, `PIC_CONFIG` or `$XDG_CONFIG_HOME/pic/config.toml`
+    pub fn path() -> Option<PathBuf> {
+        if let Some(path) = env::var_os("PIC_CONFIG") {
+            return Some(PathBuf::from(path));
+        }
+
+        let config_home = match env::var_os("XDG_CONFIG_HOME") {
+            Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+            _ => PathBuf::from(env::var_os("HOME")?).join(".config"),
+        };
+        Some(config_home.join("pic").join("config.toml"))
+    }
+
+    /// Load the config file, an absent file gives an empty config
+    pub fn load() -> Result<Self> {
+        match Config::path() {
+            Some(path) if path.is_file() => Ok(toml::from_str(&fs::read_to_string(path)?)?),
+            _ => Ok(Config::default()),
+        }
+    }
+
+    /// Resolve defaults for the current terminal
+    pub fn defaults(&self) -> Defaults {
+        let term = env::var("TERM").unwrap_or_default();
+        let program = env::var("TERM_PROGRAM").unwrap_or_default();
+        self.defaults_for(&term, &program)
+    }
+
+    /// Resolve defaults for the terminal with the given TERM and TERM_PROGRAM
+    fn defaults_for(&self, term: &str, program: &str) -> Defaults {
+        let empty = Defaults::default();
+
+        self.terminal
+            .get(program)
+            .unwrap_or(&empty)
+            .clone()
+            .or(self.terminal.get(term).unwrap_or(&empty))
+            .or(&self.defaults)
+    }
+
+    /// Fill options which weren't given on the command line or in the environment
+    pub fn apply(&self, options: &mut Options, matches: Option<&ArgMatches>) {
+        let defaults = self.defaults();
+        let is_set = |id: &str| {
+            matches.is_some_and(|matches| {
+                matches!(
+                    matches.value_source(id),
+                    Some(ValueSource::CommandLine | ValueSource::EnvVariable)
+                )
+            })
+        };
+
+        options.protocol = options.protocol.or(defaults.protocol);
+        options.cols = options.cols.or(defaults.cols);
+        options.rows = options.rows.or(defaults.rows);
+        options.spacing = options.spacing.or(defaults.spacing);
+        options.colors = options.colors.or(defaults.colors);
+        options.sort = options.sort.or(defaults.sort);
+        options.background = options.background.or(defaults.background);
+        options.max_dimension = options.max_dimension.or(defaults.max_dimension);
+        options.max_memory = options.max_memory.or(defaults.max_memory);
+        options.cache_size = options.cache_size.or(defaults.cache_size);
+        options.thumbnails = options.thumbnails.or(defaults.thumbnails);
+
+        if !is_set("alpha_threshold") {
+            if let Some(alpha_threshold) = defaults.alpha_threshold {
+                options.alpha_threshold = alpha_threshold;
+            }
+        }
+        if !is_set("upscale") && !options.upscale {
+            options.upscale = defaults.upscale.unwrap_or(false);
+        }
+        if !is_set("gif_static") && !is_set("gif_loop") && !options.gif_static && !options.gif_loop
+        {
+            match (defaults.gif_static, defaults.gif_loop) {
+                (Some(true), _) => options.set_static(),
+                (_, Some(true)) => options.set_loop(),
+                _ => (),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::{CommandFactory, FromArgMatches};
+
+    const CONFIG: &str = r#"
+        cols = 10
+        rows = 10
+        spacing = 1
+
+        [terminal.xterm-kitty]
+        cols = 20
+        rows = 20
+
+        [terminal.WezTerm]
+        cols = 30
+    "#;
+
+    fn config(content: &str) -> Config {
+        toml::from_str(content).unwrap()
+    }
+
+    /// Options as parsed from the given arguments and the environment
+    fn parse(args: &[&str]) -> (Options, ArgMatches) {
+        let matches = Options::command()
+            .try_get_matches_from(["pic"].iter().chain(args))
+            .unwrap();
+        (Options::from_arg_matches(&matches).unwrap(), matches)
+    }
+
+    #[test]
+    fn terminal_values_win_over_top_level_ones() {
+        let config = config(CONFIG);
+
+        let defaults = config.defaults_for("xterm-256color", "");
+        assert_eq!((defaults.cols, defaults.rows), (Some(10), Some(10)));
+        // TERM
+        let defaults = config.defaults_for("xterm-kitty", "");
+        assert_eq!((defaults.cols, defaults.rows), (Some(20), Some(20)));
+        // TERM_PROGRAM, then TERM for what it doesn't set
+        let defaults = config.defaults_for("xterm-kitty", "WezTerm");
+        assert_eq!((defaults.cols, defaults.rows), (Some(30), Some(20)));
+        assert_eq!(defaults.spacing, Some(1));
+    }
+
+    #[test]
+    fn flags_and_environment_win_over_the_config() {
+        let config = config("cols = 10\nrows = 10\nupscale = true\nalpha-threshold = 10");
+
+        let (mut options, matches) = parse(&["image.png"]);
+        config.apply(&mut options, Some(&matches));
+        assert_eq!((options.cols, options.rows), (Some(10), Some(10)));
+        assert!(options.upscale);
+        assert_eq!(options.alpha_threshold, 10);
+
+        let (mut options, matches) = parse(&["--cols", "5", "--alpha-threshold", "0", "x"]);
+        config.apply(&mut options, Some(&matches));
+        assert_eq!((options.cols, options.rows), (Some(5), Some(10)));
+        assert_eq!(options.alpha_threshold, 0);
+
+        // The only test changing the environment, others don't read these variables
+        env::set_var("PIC_ROWS", "4");
+        env::set_var("PIC_UPSCALE", "0");
+        let (mut env_options, env_matches) = parse(&["image.png"]);
+        let (mut cli_options, cli_matches) = parse(&["--rows", "5", "image.png"]);
+        env::remove_var("PIC_ROWS");
+        env::remove_var("PIC_UPSCALE");
+        config.apply(&mut env_options, Some(&env_matches));
+        config.apply(&mut cli_options, Some(&cli_matches));
+        assert_eq!(env_options.rows, Some(4));
+        assert!(!env_options.upscale);
+        assert_eq!(cli_options.rows, Some(5));
+    }
+
+    #[test]
+    fn unknown_keys_are_rejected() {
+        assert!(toml::from_str::<Config>("colls = 10").is_err());
+        assert!(toml::from_str::<Config>("[terminal.xterm]\nprotocl = \"kitty\"").is_err());
+        assert!(toml::from_str::<Config>("cols = \"ten\"").is_err());
+        assert!(toml::from_str::<Config>(CONFIG).is_ok());
+        assert!(toml::from_str::<Config>("").is_ok());
+    }
+}
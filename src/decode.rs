@@ -0,0 +1,726 @@
+use crate::layout;
+use crate::options::Options;
+use crate::resize::{resize, Filter};
+use crate::result::Result;
+#[cfg(feature = "svg")]
+use crate::svg;
+use crate::thumbnail::{self, ThumbnailMode};
+use crate::transform::{transform, transformed_size};
+use crate::utils::TermSize;
+use image::codecs::{gif::GifDecoder, jpeg::JpegDecoder, png::PngDecoder, webp::WebPDecoder};
+use image::io::{Limits, Reader};
+use image::{AnimationDecoder, DynamicImage, ImageDecoder, ImageFormat};
+use qcms::{DataType, Intent, Profile, Transform};
+use std::borrow::Cow;
+use std::cell::OnceCell;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Cursor, Seek};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// An image file shared by the previewing pipeline, its bytes, header dimensions
+/// and decoded pixels are only read or computed once, when first needed
+pub struct LoadedImage {
+    path: PathBuf,
+    buffer: OnceCell<Vec<u8>>,
+    size: OnceCell<(u32, u32)>,
+    decoded: OnceCell<DynamicImage>,
+}
+
+impl LoadedImage {
+    /// An image that isn't read yet
+    pub fn new(image_path: &Path) -> Self {
+        Self {
+            path: image_path.to_path_buf(),
+            buffer: OnceCell::new(),
+            size: OnceCell::new(),
+            decoded: OnceCell::new(),
+        }
+    }
+
+    /// Path of the image file
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Bytes of the image file
+    pub fn buffer(&self) -> Result<&[u8]> {
+        if let Some(buffer) = self.buffer.get() {
+            return Ok(buffer);
+        }
+        let buffer = std::fs::read(&self.path)?;
+        Ok(self.buffer.get_or_init(|| buffer))
+    }
+
+    /// Format of the image, guessed from its content then from its extension
+    pub fn format(&self) -> Result<Option<ImageFormat>> {
+        Ok(guess_format(self.buffer()?, &self.path).ok())
+    }
+
+    /// Check if the image is an SVG document
+    #[cfg(feature = "svg")]
+    pub fn is_svg(&self) -> Result<bool> {
+        match self.buffer.get() {
+            Some(buffer) => Ok(svg::is_svg(buffer)),
+            None => svg::is_svg_file(&self.path),
+        }
+    }
+
+    /// Dimensions of the image once oriented, only its header is read if the
+    /// whole file wasn't already
+    pub fn size(&self) -> Result<(u32, u32)> {
+        if let Some(size) = self.size.get() {
+            return Ok(*size);
+        }
+        let size = self.read_size()?;
+        Ok(*self.size.get_or_init(|| size))
+    }
+
+    fn read_size(&self) -> Result<(u32, u32)> {
+        #[cfg(feature = "svg")]
+        if self.is_svg()? {
+            return Ok(svg::Svg::parse(self.buffer()?)?.size());
+        }
+
+        let (image_size, orientation) = match self.buffer.get() {
+            Some(buffer) => (
+                imagesize::blob_size(buffer),
+                orientation(&mut Cursor::new(buffer)),
+            ),
+            None => (
+                imagesize::size(&self.path),
+                orientation(&mut BufReader::new(File::open(&self.path)?)),
+            ),
+        };
+        let (width, height) = match image_size {
+            Ok(image_size) => (image_size.width as u32, image_size.height as u32),
+            // Formats without magic bytes are only known by their extension
+            Err(_) => Reader::with_format(
+                Cursor::new(self.buffer()?),
+                guess_format(self.buffer()?, &self.path)?,
+            )
+            .into_dimensions()?,
+        };
+
+        // Orientations 5 to 8 swap width and height
+        match orientation {
+            5..=8 => Ok((height, width)),
+            _ => Ok((width, height)),
+        }
+    }
+
+    /// Dimensions of the image once oriented and transformed
+    pub fn display_size(&self, options: &Options) -> Result<(u32, u32)> {
+        Ok(transformed_size(self.size()?, options))
+    }
+
+    /// Decoded and transformed image at about the size it takes once fitted in the terminal,
+    /// SVGs are rasterized at that size and large JPEGs downscaled while decoding
+    ///
+    /// The image is only decoded once, for the terminal and options of the first call
+    pub fn decode(&self, term_size: &TermSize, options: &Options) -> Result<&DynamicImage> {
+        if let Some(image) = self.decoded.get() {
+            return Ok(image);
+        }
+        let image = self.decode_fitted(term_size, options)?;
+        Ok(self.decoded.get_or_init(|| image))
+    }
+
+    /// Drop the decoded pixels, for the image to be decoded again with other options
+    pub fn forget_decoded(&mut self) {
+        self.decoded.take();
+    }
+
+    fn decode_fitted(&self, term_size: &TermSize, options: &Options) -> Result<DynamicImage> {
+        #[cfg(feature = "svg")]
+        if self.is_svg()? {
+            return svg::Svg::parse(self.buffer()?)?.render_fitted(term_size, options);
+        }
+
+        let size = self.size()?;
+        let scaled = scaled_size(size, term_size, options)?;
+        if options.thumbnails.is_some() {
+            if let Some(image) = thumbnail::load(&self.path, size, scaled) {
+                return Ok(transform(image, size, options));
+            }
+        }
+
+        // Missing thumbnails are made from the image decoded at their size
+        let thumbnail_size = match options.thumbnails {
+            Some(ThumbnailMode::Write) => thumbnail::thumbnail_size(size, scaled),
+            _ => None,
+        };
+        let image = decode_buffer_scaled(
+            self.buffer()?,
+            guess_format(self.buffer()?, &self.path)?,
+            thumbnail_size.unwrap_or(scaled),
+            limits(options),
+        )?;
+        if let Some((width, height)) = thumbnail_size {
+            let thumbnail = if (image.width(), image.height()) == (width, height) {
+                Cow::Borrowed(&image)
+            } else {
+                Cow::Owned(resize(&image, width, height, Filter::Triangle, false))
+            };
+            // Thumbnails only make later previews faster, failing to save one doesn't fail this one
+            let _ = thumbnail::save(&self.path, &thumbnail);
+        }
+        Ok(transform(image, size, options))
+    }
+}
+
+/// Size to decode an image of the given size at, for its shown part to have
+/// as many pixels as the cells it takes (never more than the image itself)
+pub fn scaled_size(
+    size: (u32, u32),
+    term_size: &TermSize,
+    options: &Options,
+) -> Result<(u32, u32)> {
+    let (width, height) = transformed_size(size, options);
+    let fit = layout::fit(term_size, width, height, options)?;
+    let (col_size, row_size) = term_size.cell_size();
+
+    let scale = fit.scale(fit.cols * col_size, fit.rows * row_size).min(1.0);
+    Ok((
+        (f64::from(size.0) * scale).ceil() as u32,
+        (f64::from(size.1) * scale).ceil() as u32,
+    ))
+}
+
+/// Limits images are decoded with, huge images give an error instead of exhausting memory
+pub fn limits(options: &Options) -> Limits {
+    let mut limits = Limits::default();
+    limits.max_image_width = options.max_dimension;
+    limits.max_image_height = options.max_dimension;
+    if let Some(max_memory) = options.max_memory {
+        limits.max_alloc = Some(max_memory.saturating_mul(1024 * 1024));
+    }
+    limits
+}
+
+/// Check that an image of the given size fits in the limits, when another decoder (libsixel) decodes it
+pub fn check_limits((width, height): (u32, u32), options: &Options) -> Result {
+    let mut limits = limits(options);
+    limits.check_dimensions(width, height)?;
+    limits.reserve(u64::from(width) * u64::from(height) * 4)?;
+    Ok(())
+}
+
+/// Format of an image from its content, then from the extension of its file for
+/// formats without magic bytes (like TGA)
+pub fn guess_format(buffer: &[u8], path: &Path) -> Result<ImageFormat> {
+    Ok(image::guess_format(buffer).or_else(|_| ImageFormat::from_path(path))?)
+}
+
+/// Decode an image within the given limits, converting it to sRGB and applying its EXIF orientation
+pub fn decode_buffer(buffer: &[u8], format: ImageFormat, limits: Limits) -> Result<DynamicImage> {
+    let mut reader = Reader::with_format(Cursor::new(buffer), format);
+    reader.limits(limits);
+    Ok(corrected(buffer, reader.decode()?))
+}
+
+/// Decode an image at least as big as the given size once oriented, JPEGs are downscaled
+/// while decoding (DCT scaling) which is much faster than resizing large photos afterwards,
+/// and takes a fraction of the memory
+pub fn decode_buffer_scaled(
+    buffer: &[u8],
+    format: ImageFormat,
+    (width, height): (u32, u32),
+    mut limits: Limits,
+) -> Result<DynamicImage> {
+    if format != ImageFormat::Jpeg {
+        return decode_buffer(buffer, format, limits);
+    }
+
+    // Orientations 5 to 8 swap width and height
+    let (width, height) = match orientation(&mut Cursor::new(buffer)) {
+        5..=8 => (height, width),
+        _ => (width, height),
+    };
+    let clamp = |value: u32| value.clamp(1, u32::from(u16::MAX)) as u16;
+    let mut decoder = JpegDecoder::new(buffer)?;
+    // Dimensions are checked on the full image, memory on the downscaled one
+    decoder.set_limits(limits.clone())?;
+    decoder.scale(clamp(width), clamp(height))?;
+    limits.reserve(decoder.total_bytes())?;
+
+    Ok(corrected(buffer, DynamicImage::from_decoder(decoder)?))
+}
+
+/// Convert a decoded image to sRGB and apply its EXIF orientation
+fn corrected(buffer: &[u8], image: DynamicImage) -> DynamicImage {
+    let image = match icc_profile(buffer) {
+        Some(icc) if !is_srgb(&icc) => to_srgb(image, &icc),
+        _ => image,
+    };
+
+    apply_orientation(image, orientation(&mut Cursor::new(buffer)))
+}
+
+/// Check if an image has to go through `decode_buffer` to be displayed correctly
+pub fn needs_conversion(buffer: &[u8]) -> bool {
+    orientation(&mut Cursor::new(buffer)) != 1
+        || icc_profile(buffer).is_some_and(|icc| !is_srgb(&icc))
+}
+
+/// Frames of an animated GIF, APNG or WebP with their delays, `None` if it isn't animated
+///
+/// Frames are decoded one at a time and fitted as they come, only the fitted
+/// frames together have to fit in the memory limit
+pub fn animation_frames(
+    buffer: &[u8],
+    mut limits: Limits,
+    mut fit: impl FnMut(DynamicImage) -> Result<DynamicImage>,
+) -> Result<Option<Vec<(Duration, DynamicImage)>>> {
+    // Decoders take care of frame disposal, frames are full images
+    let frames = match image::guess_format(buffer)? {
+        ImageFormat::Gif => GifDecoder::with_limits(buffer, limits.clone())?.into_frames(),
+        ImageFormat::Png => {
+            let decoder = PngDecoder::with_limits(buffer, limits.clone())?;
+            if !decoder.is_apng() {
+                return Ok(None);
+            }
+            decoder.apng().into_frames()
+        }
+        ImageFormat::WebP => {
+            // All the frames are decoded when the decoder is created, before limits can be set
+            let image_size = imagesize::blob_size(buffer)?;
+            limits.check_dimensions(image_size.width as u32, image_size.height as u32)?;
+            limits.reserve(webp_decoded_size(buffer))?;
+            WebPDecoder::new(buffer)?.into_frames()
+        }
+        _ => return Ok(None),
+    };
+
+    let mut fitted = Vec::new();
+    for frame in frames {
+        let frame = frame?;
+        let delay = Duration::from(frame.delay());
+        let frame = fit(DynamicImage::ImageRgba8(frame.into_buffer()))?;
+        limits.reserve_usize(frame.as_bytes().len())?;
+        fitted.push((delay, frame));
+    }
+
+    if fitted.len() > 1 {
+        Ok(Some(fitted))
+    } else {
+        Ok(None)
+    }
+}
+
+/// Transform a frame of an animation and downscale it to about the size it takes
+/// once fitted in the terminal, as `LoadedImage::decode` does for still images
+pub fn fitted_frame(
+    frame: DynamicImage,
+    term_size: &TermSize,
+    options: &Options,
+) -> Result<DynamicImage> {
+    let size = (frame.width(), frame.height());
+    let frame = transform(frame, size, options);
+    let fit = layout::fit(term_size, frame.width(), frame.height(), options)?;
+    let (col_size, row_size) = term_size.cell_size();
+
+    let scale = fit.scale(fit.cols * col_size, fit.rows * row_size);
+    if scale >= 1.0 {
+        return Ok(frame);
+    }
+    let width = (f64::from(frame.width()) * scale).ceil().max(1.0) as u32;
+    let height = (f64::from(frame.height()) * scale).ceil().max(1.0) as u32;
+    let filter = options.filter.unwrap_or(Filter::Triangle);
+    Ok(resize(&frame, width, height, filter, options.linear))
+}
+
+/// Bytes taken by a decoded WebP, the canvas and every frame of animations (ANMF chunks)
+fn webp_decoded_size(buffer: &[u8]) -> u64 {
+    let u24 = |bytes: &[u8]| u64::from(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], 0]));
+    let canvas =
+        imagesize::blob_size(buffer).map_or(0, |size| size.width as u64 * size.height as u64 * 4);
+
+    // Frame width and height minus one follow its x and y offsets
+    webp_chunks(buffer)
+        .filter(|(fourcc, data)| *fourcc == b"ANMF" && data.len() >= 12)
+        .map(|(_, data)| (u24(&data[6..9]) + 1) * (u24(&data[9..12]) + 1) * 4)
+        .sum::<u64>()
+        + canvas
+}
+
+/// Check if an image has more than one frame from its headers and chunks,
+/// without decoding it (GIF, APNG and WebP)
+pub fn is_animated(buffer: &[u8]) -> bool {
+    match image::guess_format(buffer) {
+        Ok(ImageFormat::Gif) => gif_image_count(buffer) > 1,
+        Ok(ImageFormat::Png) => png::Decoder::new(buffer).read_info().is_ok_and(|reader| {
+            reader
+                .info()
+                .animation_control
+                .is_some_and(|control| control.num_frames > 1)
+        }),
+        Ok(ImageFormat::WebP) => {
+            webp_chunks(buffer)
+                .filter(|(fourcc, _)| *fourcc == b"ANMF")
+                .count()
+                > 1
+        }
+        _ => false,
+    }
+}
+
+/// Number of images in a GIF, counted by skipping over the blocks of the file
+fn gif_image_count(buffer: &[u8]) -> usize {
+    let color_table_size = |flags: u8| match flags & 0x80 {
+        0 => 0,
+        _ => 3 << ((flags & 0x07) + 1),
+    };
+    // Data is split in sub-blocks starting with their length, until an empty one
+    let skip_sub_blocks = |mut pos: usize| loop {
+        let len = usize::from(*buffer.get(pos)?);
+        pos += 1 + len;
+        if len == 0 {
+            return Some(pos);
+        }
+    };
+
+    // Header and logical screen descriptor, then the global colour table
+    let Some(&flags) = buffer.get(10) else {
+        return 0;
+    };
+    let mut pos = Some(13 + color_table_size(flags));
+    let mut count = 0;
+
+    while let Some(start) = pos {
+        pos = match buffer.get(start) {
+            // Extension, after its label
+            Some(0x21) => skip_sub_blocks(start + 2),
+            // Image descriptor, then its local colour table and the LZW code size
+            Some(0x2C) => {
+                count += 1;
+                let flags = buffer.get(start + 9).copied().unwrap_or_default();
+                skip_sub_blocks(start + 11 + color_table_size(flags))
+            }
+            // Trailer
+            _ => None,
+        };
+    }
+
+    count
+}
+
+/// Chunks of a WebP, with their fourcc
+fn webp_chunks(buffer: &[u8]) -> impl Iterator<Item = (&[u8], &[u8])> {
+    let mut pos = 12;
+
+    std::iter::from_fn(move || {
+        let fourcc = buffer.get(pos..pos + 4)?;
+        let size = u32::from_le_bytes(buffer.get(pos + 4..pos + 8)?.try_into().ok()?) as usize;
+        let data = buffer.get(pos + 8..pos + 8 + size)?;
+        // Chunks are padded to an even size
+        pos += 8 + size + size % 2;
+        Some((fourcc, data))
+    })
+}
+
+/// EXIF orientation of an image (1 to 8), 1 if there is none
+pub fn orientation(reader: &mut (impl BufRead + Seek)) -> u32 {
+    exif::Reader::new()
+        .read_from_container(reader)
+        .ok()
+        .and_then(|exif| {
+            exif.get_field(exif::Tag::Orientation, exif::In::PRIMARY)?
+                .value
+                .get_uint(0)
+        })
+        .filter(|orientation| (1..=8).contains(orientation))
+        .unwrap_or(1)
+}
+
+/// Rotate and flip an image according to its EXIF orientation
+pub fn apply_orientation(image: DynamicImage, orientation: u32) -> DynamicImage {
+    match orientation {
+        2 => image.fliph(),
+        3 => image.rotate180(),
+        4 => image.flipv(),
+        5 => image.rotate90().fliph(),
+        6 => image.rotate90(),
+        7 => image.rotate270().fliph(),
+        8 => image.rotate270(),
+        _ => image,
+    }
+}
+
+/// Embedded ICC profile of a JPEG, PNG or WebP image
+pub fn icc_profile(buffer: &[u8]) -> Option<Vec<u8>> {
+    match image::guess_format(buffer).ok()? {
+        ImageFormat::Jpeg => jpeg_icc_profile(buffer),
+        ImageFormat::Png => png::Decoder::new(buffer)
+            .read_info()
+            .ok()?
+            .info()
+            .icc_profile
+            .as_ref()
+            .map(|icc| icc.to_vec()),
+        ImageFormat::WebP => webp_icc_profile(buffer),
+        _ => None,
+    }
+}
+
+/// ICC profiles are split in APP2 segments in JPEGs
+fn jpeg_icc_profile(buffer: &[u8]) -> Option<Vec<u8>> {
+    const ICC_MARKER: &[u8] = b"ICC_PROFILE\0";
+    let mut chunks = Vec::new();
+    let mut pos = 2;
+
+    while pos + 4 <= buffer.len() && buffer[pos] == 0xFF {
+        let marker = buffer[pos + 1];
+        // Start of scan, there are no more metadata after that
+        if marker == 0xDA {
+            break;
+        }
+        let length = usize::from(u16::from_be_bytes([buffer[pos + 2], buffer[pos + 3]]));
+        let segment = buffer.get(pos + 4..pos + 2 + length)?;
+        if marker == 0xE2 && segment.starts_with(ICC_MARKER) && segment.len() > 14 {
+            chunks.push((segment[12], &segment[14..]));
+        }
+        pos += 2 + length;
+    }
+
+    if chunks.is_empty() {
+        return None;
+    }
+    chunks.sort_by_key(|(sequence, _)| *sequence);
+    Some(
+        chunks
+            .into_iter()
+            .flat_map(|(_, chunk)| chunk)
+            .copied()
+            .collect(),
+    )
+}
+
+/// ICC profiles are stored in an ICCP chunk in WebPs
+fn webp_icc_profile(buffer: &[u8]) -> Option<Vec<u8>> {
+    webp_chunks(buffer)
+        .find(|(fourcc, _)| *fourcc == b"ICCP")
+        .map(|(_, data)| data.to_vec())
+}
+
+/// Converting is only needed for profiles other than sRGB, which are told apart by
+/// their description ("sRGB IEC61966-2.1", "sRGB built-in"...)
+fn is_srgb(icc: &[u8]) -> bool {
+    icc_description(icc).is_some_and(|description| description.contains("sRGB"))
+}
+
+/// Description of an ICC profile, from its `desc` tag (ASCII in version 2 profiles,
+/// UTF-16 in version 4 ones)
+fn icc_description(icc: &[u8]) -> Option<String> {
+    let u32_at = |bytes: &[u8], offset: usize| -> Option<usize> {
+        let value = bytes.get(offset..offset.checked_add(4)?)?;
+        Some(u32::from_be_bytes(value.try_into().ok()?) as usize)
+    };
+    // Tags are listed after the 128 bytes of the header, 12 bytes each
+    let count = u32_at(icc, 128)?;
+    let entry = (0..count)
+        .map(|i| 132 + 12 * i)
+        .take_while(|&entry| entry + 12 <= icc.len())
+        .find(|&entry| &icc[entry..entry + 4] == b"desc")?;
+    let offset = u32_at(icc, entry + 4)?;
+    let tag = icc.get(offset..offset.checked_add(u32_at(icc, entry + 8)?)?)?;
+
+    match tag.get(..4)? {
+        b"desc" => {
+            let length = u32_at(tag, 8)?;
+            let text = tag.get(12..12usize.checked_add(length)?)?;
+            Some(
+                String::from_utf8_lossy(text)
+                    .trim_end_matches('\0')
+                    .to_string(),
+            )
+        }
+        b"mluc" => {
+            // The first record is enough, descriptions don't change with the language
+            let (length, offset) = (u32_at(tag, 20)?, u32_at(tag, 24)?);
+            let text = tag.get(offset..offset.checked_add(length)?)?;
+            let units: Vec<u16> = text
+                .chunks_exact(2)
+                .map(|unit| u16::from_be_bytes([unit[0], unit[1]]))
+                .collect();
+            Some(String::from_utf16_lossy(&units))
+        }
+        _ => None,
+    }
+}
+
+/// Convert an image to sRGB using its ICC profile
+fn to_srgb(image: DynamicImage, icc: &[u8]) -> DynamicImage {
+    let Some(input) = Profile::new_from_slice(icc, false) else {
+        return image;
+    };
+    let mut output = Profile::new_sRGB();
+    output.precache_output_transform();
+    let Some(transform) = Transform::new(&input, &output, DataType::RGBA8, Intent::Perceptual)
+    else {
+        return image;
+    };
+
+    let mut rgba = image.to_rgba8();
+    transform.apply(&mut rgba);
+    DynamicImage::ImageRgba8(rgba)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// ICC profile with the given tags, only what's needed to find them
+    fn icc(tags: &[(&[u8; 4], Vec<u8>)]) -> Vec<u8> {
+        let mut icc = vec![0; 128];
+        icc.extend((tags.len() as u32).to_be_bytes());
+        let mut offset = 132 + 12 * tags.len();
+        for (signature, data) in tags {
+            icc.extend(*signature);
+            icc.extend((offset as u32).to_be_bytes());
+            icc.extend((data.len() as u32).to_be_bytes());
+            offset += data.len();
+        }
+        for (_, data) in tags {
+            icc.extend(data);
+        }
+        icc
+    }
+
+    /// Version 2 description tag
+    fn desc(text: &str) -> Vec<u8> {
+        let mut tag = b"desc\0\0\0\0".to_vec();
+        tag.extend((text.len() as u32 + 1).to_be_bytes());
+        tag.extend(text.as_bytes());
+        tag.push(0);
+        tag
+    }
+
+    /// Version 4 description tag, with a single record
+    fn mluc(text: &str) -> Vec<u8> {
+        let text: Vec<u8> = text.encode_utf16().flat_map(u16::to_be_bytes).collect();
+        let mut tag = b"mluc\0\0\0\0".to_vec();
+        tag.extend(1u32.to_be_bytes());
+        tag.extend(12u32.to_be_bytes());
+        tag.extend(b"enUS");
+        tag.extend((text.len() as u32).to_be_bytes());
+        tag.extend(28u32.to_be_bytes());
+        tag.extend(text);
+        tag
+    }
+
+    /// 1x1 GIF with the given number of images
+    fn gif(images: usize) -> Vec<u8> {
+        let mut gif = b"GIF89a\x01\x00\x01\x00\x80\x00\x00\x00\x00\x00\xff\xff\xff".to_vec();
+        for _ in 0..images {
+            // Graphic control extension, image descriptor and LZW data
+            gif.extend(b"\x21\xf9\x04\x00\x0a\x00\x00\x00");
+            gif.extend(b"\x2c\x00\x00\x00\x00\x01\x00\x01\x00\x00\x02\x02\x44\x01\x00");
+        }
+        gif.push(0x3b);
+        gif
+    }
+
+    /// WebP made of the given chunks
+    fn webp(chunks: &[(&[u8], &[u8])]) -> Vec<u8> {
+        let mut body = b"WEBP".to_vec();
+        for (fourcc, data) in chunks {
+            body.extend(*fourcc);
+            body.extend((data.len() as u32).to_le_bytes());
+            body.extend(*data);
+            if data.len() % 2 == 1 {
+                body.push(0);
+            }
+        }
+        let mut webp = b"RIFF".to_vec();
+        webp.extend((body.len() as u32).to_le_bytes());
+        webp.extend(body);
+        webp
+    }
+
+    #[test]
+    fn gif_images_are_counted() {
+        assert_eq!(gif_image_count(&gif(1)), 1);
+        assert_eq!(gif_image_count(&gif(3)), 3);
+        assert!(!is_animated(&gif(1)));
+        assert!(is_animated(&gif(2)));
+
+        // Truncated files are counted up to where they stop
+        let gif = gif(2);
+        assert_eq!(gif_image_count(&gif[..gif.len() - 16]), 1);
+        assert_eq!(gif_image_count(&gif[..8]), 0);
+    }
+
+    #[test]
+    fn gif_count_matches_the_decoder() {
+        let frames = animation_frames(&gif(2), Limits::default(), Ok).unwrap();
+        assert_eq!(frames.map(|frames| frames.len()), Some(2));
+        assert!(animation_frames(&gif(1), Limits::default(), Ok)
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn webp_chunks_are_walked() {
+        let anmf = |width: u8, height: u8| {
+            [
+                0,
+                0,
+                0,
+                0,
+                0,
+                0,
+                width - 1,
+                0,
+                0,
+                height - 1,
+                0,
+                0,
+                100,
+                0,
+                0,
+                0,
+            ]
+        };
+        let (first, second) = (anmf(10, 20), anmf(4, 4));
+        let webp = webp(&[
+            (b"VP8X", &[0x12, 0, 0, 0, 9, 0, 0, 19, 0, 0]),
+            (b"ICCP", b"odd"),
+            (b"ANMF", &first),
+            (b"ANMF", &second),
+        ]);
+
+        assert_eq!(webp_chunks(&webp).count(), 4);
+        assert_eq!(webp_icc_profile(&webp), Some(b"odd".to_vec()));
+        assert!(is_animated(&webp));
+        assert_eq!(webp_decoded_size(&webp), (10 * 20 + 10 * 20 + 4 * 4) * 4);
+    }
+
+    #[test]
+    fn still_webps_arent_animated() {
+        let webp = webp(&[(b"VP8L", &[0x2f, 0, 0, 0, 0])]);
+        assert!(!is_animated(&webp));
+        assert_eq!(webp_chunks(&webp[..webp.len() - 2]).count(), 0);
+    }
+
+    #[test]
+    fn srgb_profiles_are_told_by_their_description() {
+        let copyright = b"text\0\0\0\0Copyright sRGB".to_vec();
+        assert!(is_srgb(&icc(&[(b"desc", desc("sRGB IEC61966-2.1"))])));
+        assert!(is_srgb(&icc(&[
+            (b"cprt", copyright.clone()),
+            (b"desc", mluc("sRGB built-in")),
+        ])));
+        // Other tags can mention sRGB
+        assert!(!is_srgb(&icc(&[
+            (b"cprt", copyright),
+            (b"desc", desc("Display P3")),
+        ])));
+        assert!(!is_srgb(&icc(&[(b"desc", mluc("Adobe RGB (1998)"))])));
+        assert!(!is_srgb(b"sRGB"));
+        // Truncated profiles
+        let profile = icc(&[(b"desc", desc("sRGB IEC61966-2.1"))]);
+        assert!(!is_srgb(&profile[..profile.len() - 4]));
+        assert!(!is_srgb(&profile[..136]));
+    }
+}
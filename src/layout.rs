@@ -0,0 +1,382 @@
+use crate::decode::LoadedImage;
+use crate::options::Options;
+use crate::result::Result;
+use crate::utils::{fit_in_bounds, TermSize};
+use clap::ValueEnum;
+use image::DynamicImage;
+use serde::Deserialize;
+
+/// How images are fitted in their bounds
+/// (contain keeps the whole image, cover fills the bounds and crops, fill
+/// stretches, original maps one image pixel to one terminal pixel)
+#[derive(Copy, Clone, Default, PartialEq, Eq, Hash, PartialOrd, Ord, ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FitMode {
+    #[default]
+    Contain,
+    Cover,
+    Fill,
+    Original,
+}
+
+/// Size an image takes in the terminal, and the part of it that is shown
+#[derive(Copy, Clone, Default, PartialEq, Eq)]
+pub struct Fit {
+    /// Number of cols the image takes
+    pub cols: u32,
+    /// Number of rows the image takes
+    pub rows: u32,
+    /// Shown part of the image (x, y, width, height), the whole image if `None`
+    pub crop: Option<(u32, u32, u32, u32)>,
+    /// Size of the image the crop applies to
+    size: (u32, u32),
+}
+
+impl Fit {
+    /// Shown part of an image of the given size, which can differ from the one
+    /// the fit was computed with (e.g. SVGs rasterized at another size)
+    pub fn crop_for(&self, width: u32, height: u32) -> Option<(u32, u32, u32, u32)> {
+        let (x, y, w, h) = self.crop?;
+        let scale = |value: u32, to: u32, from: u32| {
+            (u64::from(value) * u64::from(to) / u64::from(from.max(1))) as u32
+        };
+        Some((
+            scale(x, width, self.size.0),
+            scale(y, height, self.size.1),
+            scale(w, width, self.size.0).max(1),
+            scale(h, height, self.size.1).max(1),
+        ))
+    }
+
+    /// Scale to apply to the image the fit was computed with so that its shown part
+    /// takes the given number of pixels (e.g. those of the cells it takes)
+    pub fn scale(&self, width: u32, height: u32) -> f64 {
+        let (_, _, shown_width, shown_height) =
+            self.crop.unwrap_or((0, 0, self.size.0, self.size.1));
+        f64::max(
+            f64::from(width) / f64::from(shown_width.max(1)),
+            f64::from(height) / f64::from(shown_height.max(1)),
+        )
+    }
+
+    /// Only keep the shown part of an image
+    pub fn crop(&self, image: &DynamicImage) -> DynamicImage {
+        match self.crop_for(image.width(), image.height()) {
+            Some((x, y, width, height)) => image.crop_imm(x, y, width, height),
+            None => image.clone(),
+        }
+    }
+}
+
+/// Horizontal alignment of previews in their box
+#[derive(Copy, Clone, Default, PartialEq, Eq, PartialOrd, Ord, ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HorizontalAlign {
+    #[default]
+    Left,
+    Center,
+    Right,
+}
+
+/// Vertical alignment of previews in their box
+#[derive(Copy, Clone, Default, PartialEq, Eq, PartialOrd, Ord, ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum VerticalAlign {
+    #[default]
+    Top,
+    Middle,
+    Bottom,
+}
+
+/// Space left around previews in their box, in terminal cells
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct Padding {
+    pub top: u32,
+    pub right: u32,
+    pub bottom: u32,
+    pub left: u32,
+}
+
+impl std::str::FromStr for Padding {
+    type Err = String;
+
+    /// Like CSS: `all`, `vertical,horizontal` or `top,right,bottom,left`
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let error = || format!("invalid padding '{s}', expected N, V,H or T,R,B,L");
+        let values = s
+            .split(',')
+            .map(|value| value.trim().parse::<u32>())
+            .collect::<std::result::Result<Vec<u32>, _>>()
+            .map_err(|_| error())?;
+
+        match values[..] {
+            [all] => Ok(Padding {
+                top: all,
+                right: all,
+                bottom: all,
+                left: all,
+            }),
+            [vertical, horizontal] => Ok(Padding {
+                top: vertical,
+                right: horizontal,
+                bottom: vertical,
+                left: horizontal,
+            }),
+            [top, right, bottom, left] => Ok(Padding {
+                top,
+                right,
+                bottom,
+                left,
+            }),
+            _ => Err(error()),
+        }
+    }
+}
+
+impl TryFrom<String> for Padding {
+    type Error = String;
+
+    fn try_from(s: String) -> std::result::Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+/// Rectangle of terminal cells
+#[derive(Copy, Clone, Default, PartialEq, Eq)]
+pub struct Area {
+    pub x: u32,
+    pub y: u32,
+    pub cols: u32,
+    pub rows: u32,
+}
+
+/// Whether previews need to be placed in their box at all
+pub fn is_placed(options: &Options) -> bool {
+    options.align.is_some() || options.valign.is_some() || options.padding.is_some()
+}
+
+/// Fit mode to use, a scale means original size
+pub fn fit_mode(options: &Options) -> FitMode {
+    match options.scale {
+        Some(_) => FitMode::Original,
+        None => options.fit.unwrap_or_default(),
+    }
+}
+
+/// Fit an image of the given size following the fit mode and scale options
+pub fn fit(term_size: &TermSize, width: u32, height: u32, options: &Options) -> Result<Fit> {
+    let (col_size, row_size) = term_size.cell_size();
+    let (width, height) = (width.max(1), height.max(1));
+    let bound_cols = options.cols.unwrap_or(term_size.cols).max(1);
+    // Terminal prompt puts the image out of screen (rows - 1)
+    let bound_rows = options
+        .rows
+        .unwrap_or(term_size.rows.saturating_sub(1))
+        .max(1);
+    let (cols, rows, crop) = match fit_mode(options) {
+        FitMode::Contain => {
+            let (cols, rows) = fit_in_bounds(
+                term_size,
+                width,
+                height,
+                options.cols,
+                options.rows,
+                // Zoomed regions are meant to be seen bigger
+                options.upscale || options.zoom.is_some(),
+            )?;
+            (cols, rows, None)
+        }
+        FitMode::Fill => (bound_cols, bound_rows, None),
+        FitMode::Cover => {
+            let (bound_width, bound_height) = (
+                u64::from(bound_cols * col_size),
+                u64::from(bound_rows * row_size),
+            );
+            let (w, h) = (u64::from(width), u64::from(height));
+            // Keep the centre of the image, with the ratio of the bounds
+            let crop = if w * bound_height > bound_width * h {
+                let crop_width = (h * bound_width / bound_height) as u32;
+                ((width - crop_width) / 2, 0, crop_width.max(1), height)
+            } else {
+                let crop_height = (w * bound_height / bound_width) as u32;
+                (0, (height - crop_height) / 2, width, crop_height.max(1))
+            };
+            (bound_cols, bound_rows, Some(crop))
+        }
+        FitMode::Original => {
+            let scale = f64::from(options.scale.unwrap_or(100)) / 100.0;
+            let (scaled_width, scaled_height) =
+                (f64::from(width) * scale, f64::from(height) * scale);
+            let cols = (scaled_width / f64::from(col_size)).ceil().max(1.0) as u32;
+            let rows = (scaled_height / f64::from(row_size)).ceil().max(1.0) as u32;
+
+            // Only show the centre of images too big for their bounds
+            if cols <= bound_cols && rows <= bound_rows {
+                (cols, rows, None)
+            } else {
+                let (cols, rows) = (cols.min(bound_cols), rows.min(bound_rows));
+                let crop_width = (f64::from(cols * col_size) / scale).min(f64::from(width)) as u32;
+                let crop_height =
+                    (f64::from(rows * row_size) / scale).min(f64::from(height)) as u32;
+                let crop = (
+                    (width - crop_width) / 2,
+                    (height - crop_height) / 2,
+                    crop_width.max(1),
+                    crop_height.max(1),
+                );
+                (cols, rows, Some(crop))
+            }
+        }
+    };
+
+    Ok(Fit {
+        cols,
+        rows,
+        crop,
+        size: (width, height),
+    })
+}
+
+/// Number of cols and rows the image takes once fitted
+pub fn fitted_size(image: &LoadedImage, options: &Options) -> Result<(u32, u32)> {
+    let term_size = TermSize::from_options(options)?;
+    let (width, height) = image.display_size(options)?;
+    let fit = fit(&term_size, width, height, options)?;
+    Ok((fit.cols, fit.rows))
+}
+
+/// Place an image in the given box, following padding and alignment options
+///
+/// The returned area starts where the image should be drawn, its size is the
+/// bounds (padding excluded) the image should be fitted in
+pub fn place(image: &LoadedImage, options: &Options, area: Area) -> Result<Area> {
+    let padding = options.padding.unwrap_or_default();
+    let bounds = Area {
+        x: area.x + padding.left,
+        y: area.y + padding.top,
+        cols: area
+            .cols
+            .saturating_sub(padding.left + padding.right)
+            .max(1),
+        rows: area
+            .rows
+            .saturating_sub(padding.top + padding.bottom)
+            .max(1),
+    };
+
+    let mut fitted = options.clone();
+    fitted.set_size(Some(bounds.cols), Some(bounds.rows));
+    let (cols, rows) = fitted_size(image, &fitted)?;
+    let (free_cols, free_rows) = (
+        bounds.cols.saturating_sub(cols),
+        bounds.rows.saturating_sub(rows),
+    );
+
+    let x = match options.align.unwrap_or_default() {
+        HorizontalAlign::Left => 0,
+        HorizontalAlign::Center => free_cols / 2,
+        HorizontalAlign::Right => free_cols,
+    };
+    let y = match options.valign.unwrap_or_default() {
+        VerticalAlign::Top => 0,
+        VerticalAlign::Middle => free_rows / 2,
+        VerticalAlign::Bottom => free_rows,
+    };
+
+    Ok(Area {
+        x: bounds.x + x,
+        y: bounds.y + y,
+        ..bounds
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 40x12 terminal with 10x20 pixel cells
+    fn term_size() -> TermSize {
+        TermSize::new(12, 40, 400, 240)
+    }
+
+    fn options(fit: FitMode) -> Options {
+        let mut options = Options::new(Vec::new());
+        options.set_fit(Some(fit), None);
+        options
+    }
+
+    #[test]
+    fn contain_keeps_the_ratio() {
+        let fit = fit(&term_size(), 800, 400, &options(FitMode::Contain)).unwrap();
+        assert_eq!((fit.cols, fit.rows, fit.crop), (40, 10, None));
+    }
+
+    #[test]
+    fn contain_doesnt_upscale_small_images() {
+        let fit = fit(&term_size(), 100, 40, &options(FitMode::Contain)).unwrap();
+        assert_eq!((fit.cols, fit.rows), (10, 2));
+
+        let mut options = options(FitMode::Contain);
+        options.upscale = true;
+        let fit = super::fit(&term_size(), 100, 40, &options).unwrap();
+        assert_eq!((fit.cols, fit.rows), (40, 8));
+    }
+
+    #[test]
+    fn zero_cells_take_at_least_one_cell() {
+        let fit = fit(&TermSize::default(), 100, 100, &options(FitMode::Contain)).unwrap();
+        assert_eq!((fit.cols, fit.rows), (1, 1));
+
+        let mut options = options(FitMode::Fill);
+        options.set_size(Some(0), Some(0));
+        let fit = super::fit(&term_size(), 0, 0, &options).unwrap();
+        assert_eq!((fit.cols, fit.rows), (1, 1));
+    }
+
+    #[test]
+    fn fill_takes_the_whole_bounds() {
+        let fit = fit(&term_size(), 10, 1000, &options(FitMode::Fill)).unwrap();
+        // The last row is left for the prompt
+        assert_eq!((fit.cols, fit.rows, fit.crop), (40, 11, None));
+    }
+
+    #[test]
+    fn cover_crops_the_centre() {
+        let fit = fit(&term_size(), 1000, 1000, &options(FitMode::Cover)).unwrap();
+        assert_eq!((fit.cols, fit.rows), (40, 11));
+        assert_eq!(fit.crop, Some((0, 225, 1000, 550)));
+        assert_eq!(fit.scale(400, 220), 0.4);
+        assert_eq!(fit.crop_for(500, 500), Some((0, 112, 500, 275)));
+    }
+
+    #[test]
+    fn original_maps_pixels_to_pixels() {
+        let mut options = options(FitMode::Original);
+        options.set_fit(None, Some(50));
+        let fit = fit(&term_size(), 200, 100, &options).unwrap();
+        assert_eq!((fit.cols, fit.rows, fit.crop), (10, 3, None));
+
+        let fit = super::fit(&term_size(), 2000, 1000, &self::options(FitMode::Original)).unwrap();
+        assert_eq!((fit.cols, fit.rows), (40, 11));
+        assert_eq!(fit.crop, Some((800, 390, 400, 220)));
+    }
+
+    #[test]
+    fn parse_padding() {
+        let padding = |top, right, bottom, left| Padding {
+            top,
+            right,
+            bottom,
+            left,
+        };
+        assert_eq!("2".parse(), Ok(padding(2, 2, 2, 2)));
+        assert_eq!(" 1 , 2 ".parse(), Ok(padding(1, 2, 1, 2)));
+        assert_eq!("1,2,3,4".parse(), Ok(padding(1, 2, 3, 4)));
+        assert!("".parse::<Padding>().is_err());
+        assert!("1,2,3".parse::<Padding>().is_err());
+        assert!("1,2,3,4,5".parse::<Padding>().is_err());
+        assert!("-1".parse::<Padding>().is_err());
+        assert!("a,b".parse::<Padding>().is_err());
+    }
+}
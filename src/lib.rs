@@ -1,10 +1,31 @@
+/// On-disk cache of rendered previews
+pub mod cache;
+/// Captions with the file name and metadata
+pub mod caption;
+/// Defaults from the config file
+pub mod config;
+/// Shared image decoding
+pub mod decode;
+/// Fitting, alignment and padding of previews
+pub mod layout;
 /// Options needed to preview an image
 pub mod options;
+/// Input paths expansion
+pub mod paths;
 /// Previewing functions
 pub mod previewer;
+/// Image resampling
+pub mod resize;
 /// Results from previewing
 pub mod result;
 /// Previewing protocol support checking
 pub mod support;
+/// SVG rasterization
+#[cfg(feature = "svg")]
+pub mod svg;
+/// Thumbnails shared with the desktop (freedesktop thumbnail cache)
+pub mod thumbnail;
+/// Crop, zoom, rotation and flip of images
+pub mod transform;
 /// A bunch of utils
 pub mod utils;
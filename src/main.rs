@@ -1,10 +1,45 @@
-use clap::Parser;
+use clap::{error::ErrorKind, parser::ValueSource, CommandFactory, FromArgMatches};
 
 fn main() {
     let mut stdout = std::io::stdout();
-    let mut options = pic::options::Options::parse_from(wild::args());
+    let matches = pic::options::Options::command().get_matches_from(wild::args());
+    let mut options = match pic::options::Options::from_arg_matches(&matches) {
+        Ok(options) => options,
+        Err(err) => err.exit(),
+    };
+
+    // A falsey PIC_STATIC or PIC_LOOP doesn't count, and flags win over the environment
+    if options.gif_static && options.gif_loop {
+        let given = |id| matches.value_source(id) == Some(ValueSource::CommandLine);
+        match (given("gif_static"), given("gif_loop")) {
+            (true, false) => options.set_static(),
+            (false, true) => options.set_loop(),
+            _ => pic::options::Options::command()
+                .error(
+                    ErrorKind::ArgumentConflict,
+                    "the argument '--static' cannot be used with '--loop'",
+                )
+                .exit(),
+        }
+    }
+
+    match pic::config::Config::load() {
+        Ok(config) => config.apply(&mut options, Some(&matches)),
+        Err(err) => {
+            eprintln!("{err}");
+            std::process::exit(err.exit_code());
+        }
+    }
+
+    // Interrupted previews restore the terminal and remove their temporary files
+    if let Err(err) = pic::utils::install_ctrlc_handler() {
+        eprintln!("{err}");
+        std::process::exit(err.exit_code());
+    }
 
     if let Err(err) = pic::previewer::preview(&mut stdout, &mut options) {
         eprintln!("{err}");
+        // Exit codes are documented in the README so that scripts can branch on them
+        std::process::exit(err.exit_code());
     };
 }
@@ -1,17 +1,75 @@
-use crate::support::Protocol;
-use clap::{arg, command, Parser};
+use crate::caption::CaptionPosition;
+use crate::layout::{FitMode, HorizontalAlign, Padding, VerticalAlign};
+use crate::paths::Sort;
+use crate::resize::Filter;
+use crate::support::{Background, ColorMode, Protocol};
+use crate::thumbnail::ThumbnailMode;
+use crate::transform::{Flip, Region, Rotation, Zoom};
+use clap::{builder::BoolishValueParser, Parser};
 use std::path::PathBuf;
 
 /// Options for previewing an image in terminal
-#[derive(Parser)]
+#[derive(Parser, Clone)]
 #[command(author, version, about)]
 pub struct Options {
     /// Image(s) to preview
     #[arg(num_args(1..))]
     pub path: Vec<PathBuf>,
 
-    /// Previewing protocol to use
+    /// Preview images in directories recursively
+    #[arg(short = 'R', long)]
+    pub recursive: bool,
+    /// Only preview files from directories matching the glob (can be repeated)
+    #[arg(long, value_name = "GLOB")]
+    pub include: Vec<String>,
+    /// Skip files from directories matching the glob (can be repeated)
+    #[arg(long, value_name = "GLOB")]
+    pub exclude: Vec<String>,
+    /// Order of files from directories
+    #[arg(long, value_name = "ORDER", env = "PIC_SORT")]
+    pub sort: Option<Sort>,
+    /// Show a placeholder for images that can't be previewed and keep going
     #[arg(short, long)]
+    pub keep_going: bool,
+    /// Tile images in a grid with the given number of columns (automatic if omitted)
+    #[arg(
+        short,
+        long,
+        value_name = "COLUMNS",
+        num_args(0..=1),
+        default_missing_value = "0",
+        env = "PIC_GRID"
+    )]
+    pub grid: Option<u32>,
+    /// Caption images with name, dimensions, format, size, full or a template like "{name} {width}x{height}"
+    #[arg(
+        long,
+        value_name = "TEMPLATE",
+        num_args(0..=1),
+        default_missing_value = "name",
+        env = "PIC_CAPTION"
+    )]
+    pub caption: Option<String>,
+    /// Put captions above or below images
+    #[arg(long, value_name = "POSITION", env = "PIC_CAPTION_POSITION")]
+    pub caption_position: Option<CaptionPosition>,
+    /// Browse images in a full-screen viewer (n and p to move, + and - to zoom, i for info, q to quit)
+    #[arg(long, conflicts_with("grid"))]
+    pub view: bool,
+    /// Show images full-screen one after another, each for the given number of seconds
+    #[arg(
+        long,
+        value_name = "SECS",
+        value_parser = parse_seconds,
+        conflicts_with_all(["grid", "view"])
+    )]
+    pub slideshow: Option<f64>,
+    /// Show slideshow images in random order
+    #[arg(long, requires("slideshow"))]
+    pub shuffle: bool,
+
+    /// Previewing protocol to use
+    #[arg(short, long, env = "PIC_PROTOCOL")]
     pub protocol: Option<Protocol>,
     /// x position (0 is left)
     #[arg(short, long)]
@@ -19,33 +77,129 @@ pub struct Options {
     /// y position (0 is top)
     #[arg(short, long)]
     pub y: Option<u32>,
+    /// Horizontal alignment in the terminal, or in the box given by x, y, cols and rows
+    #[arg(long, value_name = "ALIGN", env = "PIC_ALIGN")]
+    pub align: Option<HorizontalAlign>,
+    /// Vertical alignment in the terminal, or in the box given by x, y, cols and rows (single image or grid)
+    #[arg(long, value_name = "ALIGN", env = "PIC_VALIGN")]
+    pub valign: Option<VerticalAlign>,
+    /// Padding around images in cells (N, V,H or T,R,B,L)
+    #[arg(long, value_name = "CELLS", env = "PIC_PADDING")]
+    pub padding: Option<Padding>,
     /// Number of cols to fit the preview in
-    #[arg(short, long)]
+    #[arg(short, long, env = "PIC_COLS")]
     pub cols: Option<u32>,
     /// Number of rows to fit the preview in
-    #[arg(short, long)]
+    #[arg(short, long, env = "PIC_ROWS")]
     pub rows: Option<u32>,
     /// Spacing between images if more than one file is provided
-    #[arg(long)]
+    #[arg(long, env = "PIC_SPACING")]
     pub spacing: Option<u32>,
     /// Upscale image if needed
-    #[arg(short, long)]
+    #[arg(
+        short,
+        long,
+        env = "PIC_UPSCALE",
+        value_parser = BoolishValueParser::new()
+    )]
     pub upscale: bool,
+    /// How to fit images in their cols and rows
+    #[arg(long, value_name = "MODE", env = "PIC_FIT")]
+    pub fit: Option<FitMode>,
+    /// Scale images to a percentage of their original size (implies --fit original)
+    #[arg(long, value_name = "PERCENT", env = "PIC_SCALE", value_parser = clap::value_parser!(u32).range(1..))]
+    pub scale: Option<u32>,
+    /// Resampling filter used when resizing images ourselves (blocks and sixel)
+    #[arg(long, value_name = "FILTER", env = "PIC_FILTER")]
+    pub filter: Option<Filter>,
+    /// Resize images in linear light, for more accurate colours
+    #[arg(long, env = "PIC_LINEAR", value_parser = BoolishValueParser::new())]
+    pub linear: bool,
+    /// Only show a region of images, in pixels
+    #[arg(long, value_name = "X,Y,W,H")]
+    pub crop: Option<Region>,
+    /// Zoom in images by a factor, around a point given in percent (e.g. 4@25,75)
+    #[arg(long, value_name = "FACTOR[@X,Y]")]
+    pub zoom: Option<Zoom>,
+    /// Rotate images clockwise
+    #[arg(long, value_name = "DEGREES")]
+    pub rotate: Option<Rotation>,
+    /// Flip images horizontally (h) or vertically (v)
+    #[arg(long, value_name = "AXIS")]
+    pub flip: Option<Flip>,
     /// Don't print newline after preview
     #[arg(short, long)]
     pub no_newline: bool,
-    /// Only show first frame of GIFs
-    #[arg(short = 's', long = "static", conflicts_with("gif_loop"))]
+    /// Only show first frame of animations (GIF, APNG, WebP)
+    #[arg(
+        short = 's',
+        long = "static",
+        env = "PIC_STATIC",
+        value_parser = BoolishValueParser::new()
+    )]
     pub gif_static: bool,
-    /// Loop GIFs infinitely
-    #[arg(short = 'l', long = "loop")]
+    /// Loop animations and slideshows infinitely
+    #[arg(
+        short = 'l',
+        long = "loop",
+        env = "PIC_LOOP",
+        value_parser = BoolishValueParser::new()
+    )]
     pub gif_loop: bool,
 
-    /// Load image with the given id (kitty only)
-    #[arg(long, value_name = "ID")]
+    /// Colour depth to use for Unicode blocks
+    #[arg(long, value_name = "MODE", env = "PIC_COLORS")]
+    pub colors: Option<ColorMode>,
+    /// Background to composite transparent pixels over (auto, none, checkerboard or #rrggbb)
+    #[arg(long, value_name = "COLOR", env = "PIC_BACKGROUND")]
+    pub background: Option<Background>,
+    /// Alpha under which a pixel is considered transparent
+    #[arg(
+        long,
+        value_name = "ALPHA",
+        default_value_t = 25,
+        env = "PIC_ALPHA_THRESHOLD"
+    )]
+    pub alpha_threshold: u8,
+
+    /// Largest width or height of images to decode, in pixels
+    #[arg(long, value_name = "PIXELS", env = "PIC_MAX_DIMENSION")]
+    pub max_dimension: Option<u32>,
+    /// Most memory decoding an image may take, in MiB (512 by default)
+    #[arg(long, value_name = "MIB", env = "PIC_MAX_MEMORY")]
+    pub max_memory: Option<u64>,
+
+    /// Don't read nor write the cache of rendered previews
+    #[arg(long, env = "PIC_NO_CACHE", value_parser = BoolishValueParser::new())]
+    pub no_cache: bool,
+    /// Empty the cache of rendered previews before previewing
+    #[arg(long)]
+    pub clear_cache: bool,
+    /// Size the cache of rendered previews is kept under, in MiB (100 by default)
+    #[arg(long, value_name = "MIB", env = "PIC_CACHE_SIZE")]
+    pub cache_size: Option<u64>,
+    /// Use the thumbnails of the desktop for small previews, and create missing ones with write
+    #[arg(long, value_name = "MODE", env = "PIC_THUMBNAILS")]
+    pub thumbnails: Option<ThumbnailMode>,
+
+    /// Number of cols of the terminal (useful when not attached to one)
+    #[arg(long, value_name = "COLS", requires("term_rows"))]
+    pub term_cols: Option<u32>,
+    /// Number of rows of the terminal (useful when not attached to one)
+    #[arg(long, value_name = "ROWS", requires("term_cols"))]
+    pub term_rows: Option<u32>,
+    /// Width of a terminal cell in pixels
+    #[arg(long, value_name = "PIXELS")]
+    pub cell_width: Option<u32>,
+    /// Height of a terminal cell in pixels
+    #[arg(long, value_name = "PIXELS")]
+    pub cell_height: Option<u32>,
+
+    /// Load image with the given id, or auto for an id reused by later runs (kitty only)
+    #[arg(long, value_name = "ID", value_parser = parse_id)]
     pub load: Option<u32>,
-    /// Display image with the given id (kitty only)
-    #[arg(long, value_name = "ID")]
+    /// Display image with the given id, or auto for the one it was loaded with (kitty only)
+    #[arg(long, value_name = "ID", value_parser = parse_id)]
     pub display: Option<u32>,
     /// Clear image with the given id (0 for all) (kitty only)
     #[arg(long, value_name = "ID")]
@@ -57,28 +211,114 @@ impl Options {
     pub fn new(path: Vec<PathBuf>) -> Self {
         Self {
             path,
+            recursive: false,
+            include: Vec::new(),
+            exclude: Vec::new(),
+            sort: None,
+            keep_going: false,
+            grid: None,
+            caption: None,
+            caption_position: None,
+            view: false,
+            slideshow: None,
+            shuffle: false,
             protocol: None,
             x: None,
             y: None,
+            align: None,
+            valign: None,
+            padding: None,
             cols: None,
             rows: None,
             spacing: None,
             upscale: false,
+            fit: None,
+            scale: None,
+            filter: None,
+            linear: false,
+            crop: None,
+            zoom: None,
+            rotate: None,
+            flip: None,
             gif_static: false,
             gif_loop: false,
             no_newline: false,
+            colors: None,
+            background: None,
+            alpha_threshold: 25,
+            max_dimension: None,
+            max_memory: None,
+            no_cache: false,
+            clear_cache: false,
+            cache_size: None,
+            thumbnails: None,
+            term_cols: None,
+            term_rows: None,
+            cell_width: None,
+            cell_height: None,
             load: None,
             display: None,
             clear: None,
         }
     }
 
+    /// Expand directories recursively, only keeping files matching the globs
+    pub fn set_recursive(&mut self, include: Vec<String>, exclude: Vec<String>) {
+        self.recursive = true;
+        self.include = include;
+        self.exclude = exclude;
+    }
+
+    /// Set order of files from directories
+    pub fn set_sort(&mut self, sort: Option<Sort>) {
+        self.sort = sort;
+    }
+
+    /// Keep going when an image can't be previewed
+    pub fn keep_going(&mut self) {
+        self.keep_going = true;
+    }
+
+    /// Tile images in a grid with the given number of columns (0 for automatic)
+    pub fn set_grid(&mut self, columns: Option<u32>) {
+        self.grid = columns;
+    }
+
+    /// Caption images with the given template, above or below them
+    pub fn set_caption(&mut self, caption: Option<String>, position: Option<CaptionPosition>) {
+        self.caption = caption;
+        self.caption_position = position;
+    }
+
+    /// Browse images in a full-screen viewer
+    pub fn view(&mut self) {
+        self.view = true;
+    }
+
+    /// Show images full-screen one after another, each for the given number of seconds
+    pub fn set_slideshow(&mut self, seconds: Option<f64>, shuffle: bool) {
+        self.slideshow = seconds;
+        self.shuffle = shuffle;
+    }
+
     /// Set position of images in the terminal
     pub fn set_position(&mut self, x: Option<u32>, y: Option<u32>) {
         self.x = x;
         self.y = y;
     }
 
+    /// Set alignment and padding of images in their box
+    pub fn set_alignment(
+        &mut self,
+        align: Option<HorizontalAlign>,
+        valign: Option<VerticalAlign>,
+        padding: Option<Padding>,
+    ) {
+        self.align = align;
+        self.valign = valign;
+        self.padding = padding;
+    }
+
     /// Set size of images in the terminal
     pub fn set_size(&mut self, cols: Option<u32>, rows: Option<u32>) {
         self.cols = cols;
@@ -95,24 +335,96 @@ impl Options {
         self.upscale = true;
     }
 
+    /// Set how images are fitted, and their scale in original size mode
+    pub fn set_fit(&mut self, fit: Option<FitMode>, scale: Option<u32>) {
+        self.fit = fit;
+        self.scale = scale;
+    }
+
+    /// Set resampling filter, and whether to resize in linear light
+    pub fn set_filter(&mut self, filter: Option<Filter>, linear: bool) {
+        self.filter = filter;
+        self.linear = linear;
+    }
+
+    /// Set transformations applied to images before displaying them
+    pub fn set_transform(
+        &mut self,
+        crop: Option<Region>,
+        zoom: Option<Zoom>,
+        rotate: Option<Rotation>,
+        flip: Option<Flip>,
+    ) {
+        self.crop = crop;
+        self.zoom = zoom;
+        self.rotate = rotate;
+        self.flip = flip;
+    }
+
     /// No newline after image
     pub fn no_newline(&mut self) {
         self.no_newline = true;
     }
 
-    /// Set GIFs to be static
+    /// Set animations to be static
     pub fn set_static(&mut self) {
         self.gif_static = true;
         self.gif_loop = false;
     }
 
-    /// Set GIFs to loop
+    /// Set animations to loop
     pub fn set_loop(&mut self) {
         self.gif_static = false;
         self.gif_loop = true;
     }
 
-    /// Set options for kitty
+    /// Set colour depth of Unicode blocks
+    pub fn set_colors(&mut self, colors: Option<ColorMode>) {
+        self.colors = colors;
+    }
+
+    /// Set background to composite transparent pixels over, and the alpha under which pixels are transparent
+    pub fn set_background(&mut self, background: Option<Background>, alpha_threshold: u8) {
+        self.background = background;
+        self.alpha_threshold = alpha_threshold;
+    }
+
+    /// Set largest width or height of images to decode, and most memory decoding them may take in MiB
+    pub fn set_limits(&mut self, max_dimension: Option<u32>, max_memory: Option<u64>) {
+        self.max_dimension = max_dimension;
+        self.max_memory = max_memory;
+    }
+
+    /// Don't use the cache of rendered previews
+    pub fn no_cache(&mut self) {
+        self.no_cache = true;
+    }
+
+    /// Set size the cache of rendered previews is kept under, in MiB
+    pub fn set_cache_size(&mut self, cache_size: Option<u64>) {
+        self.cache_size = cache_size;
+    }
+
+    /// Set how the thumbnails of the desktop are used
+    pub fn set_thumbnails(&mut self, thumbnails: Option<ThumbnailMode>) {
+        self.thumbnails = thumbnails;
+    }
+
+    /// Set virtual terminal geometry, to generate output for a terminal that is not attached
+    pub fn set_term_size(
+        &mut self,
+        cols: Option<u32>,
+        rows: Option<u32>,
+        cell_width: Option<u32>,
+        cell_height: Option<u32>,
+    ) {
+        self.term_cols = cols;
+        self.term_rows = rows;
+        self.cell_width = cell_width;
+        self.cell_height = cell_height;
+    }
+
+    /// Set options for kitty, id 0 to load or display picks the id later runs reuse
     pub fn set_kitty(&mut self, load: Option<u32>, display: Option<u32>, clear: Option<u32>) {
         if self.protocol == Some(Protocol::Kitty) {
             self.load = load;
@@ -121,3 +433,20 @@ impl Options {
         }
     }
 }
+
+/// Parse a kitty image id, auto is given as 0
+fn parse_id(value: &str) -> std::result::Result<u32, String> {
+    match value {
+        "auto" => Ok(0),
+        _ => value.parse().map_err(|err| format!("{err}")),
+    }
+}
+
+/// Parse a positive number of seconds
+fn parse_seconds(value: &str) -> std::result::Result<f64, String> {
+    match value.parse::<f64>() {
+        Ok(seconds) if seconds.is_finite() && seconds > 0.0 => Ok(seconds),
+        Ok(_) => Err("must be a positive number of seconds".to_string()),
+        Err(err) => Err(format!("{err}")),
+    }
+}
@@ -0,0 +1,204 @@
+use crate::options::Options;
+use crate::result::Result;
+use clap::ValueEnum;
+use glob::Pattern;
+use serde::Deserialize;
+use std::cmp::Ordering;
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+/// Order of the files found in directories
+/// (natural compares numbers by value, mtime is oldest first, size is smallest first)
+#[derive(Copy, Clone, Default, PartialEq, Eq, PartialOrd, Ord, ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Sort {
+    #[default]
+    Name,
+    Natural,
+    Mtime,
+    Size,
+}
+
+/// Include and exclude glob patterns
+struct Filter {
+    include: Vec<Pattern>,
+    exclude: Vec<Pattern>,
+}
+
+impl Filter {
+    fn new(include: &[String], exclude: &[String]) -> Result<Self> {
+        let compile = |patterns: &[String]| {
+            patterns
+                .iter()
+                .map(|pattern| Pattern::new(pattern))
+                .collect::<std::result::Result<Vec<Pattern>, _>>()
+        };
+
+        Ok(Self {
+            include: compile(include)?,
+            exclude: compile(exclude)?,
+        })
+    }
+
+    /// Patterns are matched against the file name and the whole path
+    fn matches(&self, path: &Path) -> bool {
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        let matches = |pattern: &Pattern| pattern.matches(&name) || pattern.matches_path(path);
+
+        (self.include.is_empty() || self.include.iter().any(matches))
+            && !self.exclude.iter().any(matches)
+    }
+}
+
+/// Expand directories into the images they contain, other paths are kept as they are
+pub fn expand(options: &Options) -> Result<Vec<PathBuf>> {
+    let filter = Filter::new(&options.include, &options.exclude)?;
+    let mut image_paths = Vec::new();
+
+    for path in &options.path {
+        if path.is_dir() {
+            let mut files = Vec::new();
+            walk(path, options.recursive, &filter, &mut files)
+                .and_then(|_| sort(&mut files, options.sort.unwrap_or_default()))
+                .map_err(|err| err.with_path(path))?;
+            image_paths.extend(files);
+        } else {
+            image_paths.push(path.clone());
+        }
+    }
+
+    Ok(image_paths)
+}
+
+fn walk(dir: &Path, recursive: bool, filter: &Filter, files: &mut Vec<PathBuf>) -> Result {
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let path = entry.path();
+        // Skip hidden files and directories
+        if entry.file_name().to_string_lossy().starts_with('.') {
+            continue;
+        }
+
+        // Symlinks to directories aren't followed, avoiding loops
+        if entry.file_type()?.is_dir() {
+            if recursive {
+                walk(&path, recursive, filter, files)?;
+            }
+        } else if path.is_file() && filter.matches(&path) && is_image(&path) {
+            files.push(path);
+        }
+    }
+
+    Ok(())
+}
+
+/// Check if a file is an image by its magic bytes, whatever its extension
+pub fn is_image(path: &Path) -> bool {
+    let mut buffer = Vec::with_capacity(1024);
+    let read = File::open(path).and_then(|file| file.take(1024).read_to_end(&mut buffer));
+
+    #[cfg(feature = "svg")]
+    if read.is_ok() && crate::svg::is_svg(&buffer) {
+        return true;
+    }
+
+    read.is_ok() && image::guess_format(&buffer).is_ok()
+}
+
+fn sort(files: &mut [PathBuf], order: Sort) -> Result {
+    match order {
+        Sort::Name => files.sort(),
+        Sort::Natural => {
+            files.sort_by(|a, b| natural_cmp(&a.to_string_lossy(), &b.to_string_lossy()))
+        }
+        Sort::Mtime => {
+            let mut keys = Vec::with_capacity(files.len());
+            for file in files.iter() {
+                keys.push((fs::metadata(file)?.modified()?, file.clone()));
+            }
+            keys.sort();
+            files.clone_from_slice(&keys.into_iter().map(|(_, file)| file).collect::<Vec<_>>());
+        }
+        Sort::Size => {
+            let mut keys = Vec::with_capacity(files.len());
+            for file in files.iter() {
+                keys.push((fs::metadata(file)?.len(), file.clone()));
+            }
+            keys.sort();
+            files.clone_from_slice(&keys.into_iter().map(|(_, file)| file).collect::<Vec<_>>());
+        }
+    }
+
+    Ok(())
+}
+
+/// Compare strings with digit runs compared by value, "img2" < "img10"
+fn natural_cmp(a: &str, b: &str) -> Ordering {
+    let (mut a, mut b) = (a.chars().peekable(), b.chars().peekable());
+
+    loop {
+        match (a.peek().copied(), b.peek().copied()) {
+            (None, None) => return Ordering::Equal,
+            (None, Some(_)) => return Ordering::Less,
+            (Some(_), None) => return Ordering::Greater,
+            (Some(x), Some(y)) if x.is_ascii_digit() && y.is_ascii_digit() => {
+                let take_number = |chars: &mut std::iter::Peekable<std::str::Chars>| {
+                    let mut digits = String::new();
+                    while let Some(digit) = chars.next_if(char::is_ascii_digit) {
+                        digits.push(digit);
+                    }
+                    digits
+                };
+                let (x, y) = (take_number(&mut a), take_number(&mut b));
+                let (x, y) = (x.trim_start_matches('0'), y.trim_start_matches('0'));
+                let ordering = x.len().cmp(&y.len()).then_with(|| x.cmp(y));
+                if ordering != Ordering::Equal {
+                    return ordering;
+                }
+            }
+            (Some(x), Some(y)) => {
+                if x != y {
+                    return x.cmp(&y);
+                }
+                a.next();
+                b.next();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn numbers_are_compared_by_value() {
+        assert_eq!(natural_cmp("img2", "img10"), Ordering::Less);
+        assert_eq!(natural_cmp("img10", "img9"), Ordering::Greater);
+        assert_eq!(natural_cmp("2", "10"), Ordering::Less);
+        // Longer than any integer type
+        assert_eq!(
+            natural_cmp("a123456789012345678901234567890", "a99"),
+            Ordering::Greater
+        );
+    }
+
+    #[test]
+    fn leading_zeros_dont_count() {
+        assert_eq!(natural_cmp("img007", "img7"), Ordering::Equal);
+        assert_eq!(natural_cmp("img007", "img10"), Ordering::Less);
+    }
+
+    #[test]
+    fn mixed_digits_and_letters() {
+        assert_eq!(natural_cmp("a10b2", "a10b10"), Ordering::Less);
+        assert_eq!(natural_cmp("file10a", "file10b"), Ordering::Less);
+        assert_eq!(natural_cmp("x9y", "x10"), Ordering::Less);
+        // Digits come before letters
+        assert_eq!(natural_cmp("1abc", "abc"), Ordering::Less);
+        assert_eq!(natural_cmp("abc", "abc1"), Ordering::Less);
+        assert_eq!(natural_cmp("", "a"), Ordering::Less);
+        assert_eq!(natural_cmp("same", "same"), Ordering::Equal);
+    }
+}
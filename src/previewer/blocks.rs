@@ -1,16 +1,17 @@
+use crate::cache::write_cached;
+use crate::decode::{animation_frames, is_animated, limits, LoadedImage};
+use crate::layout::{self, Fit};
 use crate::options::Options;
+use crate::resize::{resize, Filter};
 use crate::result::Result;
+use crate::support::{Background, ColorMode};
+use crate::transform::transform;
 use crate::utils::{
-    ansi_color, fit_in_bounds, handle_spacing, hide_cursor, move_cursor, move_cursor_up,
-    pixel_is_transparent, resize, show_cursor, CtrlcHandler, TermSize,
+    ansi_color, composite, handle_spacing, move_cursor, move_cursor_up, pixel_is_transparent,
+    play_animation, TermSize,
 };
-use crossbeam_channel::select;
-use image::codecs::gif::GifDecoder;
-use image::{AnimationDecoder, DynamicImage, ImageFormat};
-use std::fs::File;
-use std::io::{Read, Write};
-use std::path::PathBuf;
-// use std::thread;
+use image::DynamicImage;
+use std::io::Write;
 use std::time::Duration;
 
 const ANSI_CLEAR: &str = "\x1b[m";
@@ -25,8 +26,9 @@ fn write_color_block(stdout: &mut impl Write, block: &str, ansi_bg: &str, ansi_f
 
 /// this function should only print a 'ready to display' frame
 fn display_frame(stdout: &mut impl Write, image: &DynamicImage, options: &Options) -> Result {
-    let rgba = image.to_rgba8();
-    let term_size = TermSize::from_ioctl()?;
+    let rgba = composite(image, Background::choose(options), options.alpha_threshold);
+    let term_size = TermSize::from_options(options)?;
+    let colors = ColorMode::choose(options);
 
     move_cursor(stdout, options.x, options.y)?;
     let mut backgrounds = vec![[0; 4]; rgba.width() as usize];
@@ -43,19 +45,22 @@ fn display_frame(stdout: &mut impl Write, image: &DynamicImage, options: &Option
                     let rgb_fg = pixel.2 .0;
                     let rgb_bg = backgrounds[c];
 
-                    match (pixel_is_transparent(rgb_fg), pixel_is_transparent(rgb_bg)) {
+                    match (
+                        pixel_is_transparent(rgb_fg, options.alpha_threshold),
+                        pixel_is_transparent(rgb_bg, options.alpha_threshold),
+                    ) {
                         (true, true) => write_color_block(stdout, " ", "", "")?,
                         (true, false) => {
-                            let ansi_fg = ansi_color(rgb_bg, false);
+                            let ansi_fg = ansi_color(rgb_bg, false, colors);
                             write_color_block(stdout, TOP_BLOCK, "", &ansi_fg)?;
                         }
                         (false, true) => {
-                            let ansi_fg = ansi_color(rgb_fg, false);
+                            let ansi_fg = ansi_color(rgb_fg, false, colors);
                             write_color_block(stdout, BOTTOM_BLOCK, "", &ansi_fg)?;
                         }
                         (false, false) => {
-                            let ansi_bg = ansi_color(rgb_bg, true);
-                            let ansi_fg = ansi_color(rgb_fg, false);
+                            let ansi_bg = ansi_color(rgb_bg, true, colors);
+                            let ansi_fg = ansi_color(rgb_fg, false, colors);
                             write_color_block(stdout, BOTTOM_BLOCK, &ansi_bg, &ansi_fg)?;
                         }
                     }
@@ -65,7 +70,7 @@ fn display_frame(stdout: &mut impl Write, image: &DynamicImage, options: &Option
 
         if is_bg {
             move_cursor(stdout, options.x, None)?;
-        } else if r != (rgba.height() - 1) as u32 || !options.no_newline {
+        } else if r != rgba.height() - 1 || !options.no_newline {
             stdout.write_all(b"\n")?;
         };
     }
@@ -73,78 +78,75 @@ fn display_frame(stdout: &mut impl Write, image: &DynamicImage, options: &Option
     Ok(())
 }
 
-fn display_image(stdout: &mut impl Write, buffer: &[u8], options: &Options) -> Result {
-    let image = image::load_from_memory(buffer)?;
-    let (width, height) = (image.width(), image.height());
-    let (cols, rows) = fit_in_bounds(width, height, options.cols, options.rows, options.upscale)?;
-
-    display_frame(stdout, &resize(&image, cols, rows * 2), options)
+/// Crop and resize an image, or a frame, to the cols and rows it takes
+fn fit_image(image: &DynamicImage, fit: &Fit, options: &Options) -> DynamicImage {
+    let filter = options.filter.unwrap_or(Filter::Triangle);
+    resize(
+        &fit.crop(image),
+        fit.cols,
+        fit.rows * 2,
+        filter,
+        options.linear,
+    )
 }
 
-fn display_gif(stdout: &mut impl Write, buffer: &[u8], options: &Options) -> Result {
-    if options.gif_static {
-        display_image(stdout, buffer, options)
-    } else {
-        let frames: Vec<(Duration, DynamicImage)> = GifDecoder::new(buffer)?
-            .into_frames()
-            .collect_frames()?
-            .iter()
-            .map(|frame| {
-                let delay = Duration::from(frame.delay());
-                let image = &DynamicImage::ImageRgba8(frame.clone().into_buffer());
-                let (width, height) = (image.width(), image.height());
-                let (cols, rows) =
-                    fit_in_bounds(width, height, options.cols, options.rows, options.upscale)
-                        .unwrap_or_default();
-
-                (delay, resize(image, cols, rows * 2))
-            })
-            .collect();
-
-        // Prevents cursor flickering
-        let handler = CtrlcHandler::new()?;
-        hide_cursor(stdout)?;
-
-        // We need to move cursor up, except on very first frame
-        let mut first_frame = true;
+fn display_image(
+    stdout: &mut impl Write,
+    image: &DynamicImage,
+    (width, height): (u32, u32),
+    options: &Options,
+) -> Result {
+    let term_size = TermSize::from_options(options)?;
+    let fit = layout::fit(&term_size, width, height, options)?;
 
-        'gif: loop {
-            for (delay, frame) in &frames {
-                select! {
-                    default(*delay) => {
-                        if first_frame {
-                            first_frame = false;
-                        } else {
-                            move_cursor_up(stdout, frame.height() / 2 - 1)?;
-                        }
-
-                        display_frame(stdout, frame, options)?;
-                    },
-                    recv(handler.receiver) -> _ => {
-                        break 'gif;
-                    }
-                }
-            }
+    display_frame(stdout, &fit_image(image, &fit, options), options)
+}
 
-            if !options.gif_loop {
-                break 'gif;
+fn display_animation(
+    stdout: &mut impl Write,
+    frames: Vec<(Duration, DynamicImage)>,
+    options: &Options,
+) -> Result {
+    play_animation(
+        stdout,
+        &frames,
+        options.gif_loop,
+        |stdout, frame, first_frame| {
+            // We need to move cursor up, except on very first frame
+            if !first_frame {
+                move_cursor_up(stdout, frame.height() / 2 - 1)?;
             }
-        }
-
-        show_cursor(stdout)?;
-        handler.sender.send(true)?;
-        Ok(())
-    }
+            display_frame(stdout, frame, options)
+        },
+    )
 }
 
-pub fn preview(stdout: &mut impl Write, image_path: &PathBuf, options: &Options) -> Result {
-    let mut image = File::open(image_path)?;
-    let mut buffer = Vec::new();
-    image.read_to_end(&mut buffer)?;
-
-    match image::guess_format(&buffer)? {
-        ImageFormat::Gif => display_gif(stdout, &buffer, options)?,
-        _ => display_image(stdout, &buffer, options)?,
+pub fn preview(stdout: &mut impl Write, image: &LoadedImage, options: &Options) -> Result {
+    let frames = if !options.gif_static && is_animated(image.buffer()?) {
+        let term_size = TermSize::from_options(options)?;
+        animation_frames(image.buffer()?, limits(options), |frame| {
+            let size = (frame.width(), frame.height());
+            let frame = transform(frame, size, options);
+            let fit = layout::fit(&term_size, frame.width(), frame.height(), options)?;
+            Ok(fit_image(&frame, &fit, options))
+        })?
+    } else {
+        None
+    };
+    match frames {
+        Some(frames) => display_animation(stdout, frames, options)?,
+        None => {
+            // Colours can depend on the terminal, renders too
+            let kind = match ColorMode::choose(options) {
+                ColorMode::Truecolor => "blocks truecolor",
+                ColorMode::Ansi256 => "blocks ansi256",
+            };
+            write_cached(stdout, image, kind, options, |stdout| {
+                let term_size = TermSize::from_options(options)?;
+                let decoded = image.decode(&term_size, options)?;
+                display_image(stdout, decoded, image.display_size(options)?, options)
+            })?;
+        }
     }
 
     handle_spacing(stdout, options.spacing)?;
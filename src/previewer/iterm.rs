@@ -1,30 +1,120 @@
+use crate::cache::write_cached;
+use crate::decode::{
+    animation_frames, decode_buffer, decode_buffer_scaled, fitted_frame, guess_format, is_animated,
+    limits, needs_conversion, scaled_size, LoadedImage,
+};
+use crate::layout::{self, FitMode};
 use crate::options::Options;
 use crate::result::Result;
-use crate::utils::{convert_to_image_buffer, fit_in_bounds, handle_spacing, move_cursor};
+use crate::support::Background;
+#[cfg(feature = "svg")]
+use crate::svg;
+use crate::transform::{is_transformed, transform, transformed_size};
+use crate::utils::{
+    composite, convert_to_gif_buffer, convert_to_image_buffer, handle_spacing, move_cursor,
+    TermSize,
+};
 use base64::{engine::general_purpose, Engine as _};
-use image::ImageFormat;
-use std::fs::File;
-use std::io::{Read, Write};
-use std::path::PathBuf;
-
-fn display(stdout: &mut impl Write, image_path: &PathBuf, options: &mut Options) -> Result {
-    let mut image = File::open(image_path)?;
-    let mut buffer = Vec::new();
-    image.read_to_end(&mut buffer)?;
-
-    let image_size = imagesize::size(image_path)?;
-    let (width, height) = (image_size.width as u32, image_size.height as u32);
-    let (cols, rows) = fit_in_bounds(width, height, options.cols, options.rows, options.upscale)?;
-
-    let data = match (image::guess_format(&buffer)?, options.gif_static) {
-        (ImageFormat::Gif, true) => {
-            let gif = image::load_from_memory(&buffer)?;
+use image::{DynamicImage, ImageFormat};
+use std::borrow::Cow;
+use std::io::Write;
+
+fn display(stdout: &mut impl Write, image: &LoadedImage, options: &Options) -> Result {
+    // The whole file is sent, reading it first saves reading its header separately
+    let buffer = Cow::Borrowed(image.buffer()?);
+    let size = image.size()?;
+    let (width, height) = transformed_size(size, options);
+    let term_size = TermSize::from_options(options)?;
+    let fit = layout::fit(&term_size, width, height, options)?;
+    let (cols, rows) = (fit.cols, fit.rows);
+    // iTerm can't transform or crop images, we do it ourselves
+    let edited = is_transformed(options) || fit.crop.is_some();
+
+    let options = Cow::Borrowed(options);
+    // iTerm can't display SVGs, send them rasterized (and already transformed)
+    #[cfg(feature = "svg")]
+    let (buffer, options) = if image.is_svg()? {
+        let image = svg::Svg::parse(&buffer)?.render_fitted(&term_size, &options)?;
+        let mut options = options.into_owned();
+        options.set_transform(None, None, None, None);
+        let buffer = convert_to_image_buffer(&image, image.width(), image.height())?;
+        (Cow::Owned(buffer), Cow::Owned(options))
+    } else {
+        (buffer, options)
+    };
+    let options = options.as_ref();
+    let edit = |image: DynamicImage| fit.crop(&transform(image, size, options));
+
+    let background = Background::choose(options);
+    // iTerm only animates GIFs, other animations (and edited GIFs) are converted
+    let convert = !options.gif_static
+        && match guess_format(&buffer, image.path())? {
+            ImageFormat::Png | ImageFormat::WebP => true,
+            ImageFormat::Gif => edited,
+            _ => false,
+        }
+        && is_animated(&buffer);
+    let frames = if convert {
+        animation_frames(&buffer, limits(options), |frame| {
+            let frame = fitted_frame(frame, &term_size, options)?;
+            Ok(composite(&fit.crop(&frame), background, options.alpha_threshold).into())
+        })?
+    } else {
+        None
+    };
+    let animated = frames.is_some();
+    let buffer = match frames {
+        Some(frames) => Cow::Owned(convert_to_gif_buffer(&frames)?),
+        None => buffer,
+    };
+
+    let data = match guess_format(&buffer, image.path())? {
+        ImageFormat::Gif if animated || !(options.gif_static || edited) => {
+            general_purpose::STANDARD.encode(buffer)
+        }
+        ImageFormat::Gif => {
+            let gif = edit(decode_buffer(&buffer, ImageFormat::Gif, limits(options))?);
+            let gif = DynamicImage::from(composite(&gif, background, options.alpha_threshold));
+            let (width, height) = (gif.width(), gif.height());
             general_purpose::STANDARD.encode(convert_to_image_buffer(&gif, width, height)?)
         }
-        _ => general_purpose::STANDARD.encode(buffer),
+        format => {
+            // Orient, convert and composite images ourselves, otherwise send them untouched
+            let converted = needs_conversion(&buffer);
+            let transparent = format != ImageFormat::Jpeg && background != Background::None;
+            let image = if converted || transparent || edited {
+                let scaled = scaled_size(size, &term_size, options)?;
+                Some(decode_buffer_scaled(
+                    &buffer,
+                    format,
+                    scaled,
+                    limits(options),
+                )?)
+            } else {
+                None
+            };
+
+            match image {
+                Some(image) if converted || edited || image.color().has_alpha() => {
+                    let image = DynamicImage::from(composite(
+                        &edit(image),
+                        background,
+                        options.alpha_threshold,
+                    ));
+                    let (width, height) = (image.width(), image.height());
+                    general_purpose::STANDARD
+                        .encode(convert_to_image_buffer(&image, width, height)?)
+                }
+                _ => general_purpose::STANDARD.encode(buffer),
+            }
+        }
     };
 
-    let command = format!("\x1b]1337;File=width={cols};height={rows};inline=1;:{data}\x07");
+    // Stretched and cropped images fill their cols and rows exactly
+    let preserve_ratio = u8::from(fit.crop.is_none() && layout::fit_mode(options) != FitMode::Fill);
+    let command = format!(
+        "\x1b]1337;File=width={cols};height={rows};preserveAspectRatio={preserve_ratio};inline=1;:{data}\x07"
+    );
 
     move_cursor(stdout, options.x, options.y)?;
     stdout.write_all(command.as_bytes())?;
@@ -37,8 +127,10 @@ fn display(stdout: &mut impl Write, image_path: &PathBuf, options: &mut Options)
     Ok(())
 }
 
-pub fn preview(stdout: &mut impl Write, image_path: &PathBuf, options: &mut Options) -> Result {
-    display(stdout, image_path, options)?;
+pub fn preview(stdout: &mut impl Write, image: &LoadedImage, options: &Options) -> Result {
+    write_cached(stdout, image, "iterm", options, |stdout| {
+        display(stdout, image, options)
+    })?;
     handle_spacing(stdout, options.spacing)?;
     Ok(())
 }
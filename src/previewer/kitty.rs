@@ -1,16 +1,26 @@
+use crate::cache::{write_cached, Cache};
+use crate::decode::LoadedImage;
+use crate::layout::{self, Fit};
 use crate::options::Options;
 use crate::result::Result;
+use crate::support::query_terminal;
 use crate::utils::{
-    create_temp_file, fit_in_bounds, handle_spacing, move_cursor, save_in_temp_file,
+    create_temp_file, handle_spacing, keep_temp_file, move_cursor, save_in_temp_file, TermSize,
+    KITTY_PREFIX,
 };
 use base64::{engine::general_purpose, Engine as _};
-use image::io::Reader;
+use std::collections::hash_map::RandomState;
+use std::env;
+use std::fs;
+use std::hash::{BuildHasher, Hash, Hasher};
 use std::io::Write;
 use std::path::PathBuf;
+use std::time::SystemTime;
 
-const KITTY_PREFIX: &str = "pic.tty-graphics-protocol.";
 const PROTOCOL_START: &str = "\x1b_G";
 const PROTOCOL_END: &str = "\x1b\\";
+/// Number of images whose ids are remembered
+const MAX_IDS: usize = 256;
 
 fn send_graphics_command(
     stdout: &mut impl Write,
@@ -29,6 +39,112 @@ fn send_graphics_command(
     Ok(())
 }
 
+/// Send pixels through a temporary file, which the terminal removes once it has read it
+fn transmit(stdout: &mut impl Write, command: &str, pixels: &[u8], newline: bool) -> Result {
+    let mut tempfile = create_temp_file(KITTY_PREFIX, "")?;
+    save_in_temp_file(pixels, tempfile.as_file_mut())?;
+    send_graphics_command(stdout, command, tempfile.path().to_str(), newline)?;
+    // Only kept once sent, files of failed previews are removed
+    keep_temp_file(tempfile)
+}
+
+/// Ids of the images sent to terminals, kept across runs so that an image the
+/// terminal still holds is placed again instead of being retransmitted
+///
+/// Images belong to a terminal window, ids are remembered for each of them
+struct ImageIds {
+    terminal: String,
+    /// Terminal, hash of the content, id and dimensions of the transmitted pixels, oldest first
+    entries: Vec<(String, String, u32, u32, u32)>,
+}
+
+impl ImageIds {
+    /// File the ids are kept in, `$XDG_STATE_HOME/pic/kitty-ids` or `~/.local/state/pic/kitty-ids`
+    fn path() -> Option<PathBuf> {
+        let state_home = match env::var_os("XDG_STATE_HOME") {
+            Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+            _ => PathBuf::from(env::var_os("HOME")?).join(".local/state"),
+        };
+        Some(state_home.join("pic").join("kitty-ids"))
+    }
+
+    /// Ids remembered so far, none if they can't be read
+    fn open() -> Self {
+        let terminal = ["KITTY_PID", "KITTY_WINDOW_ID", "WEZTERM_PANE"]
+            .map(|var| env::var(var).unwrap_or_default())
+            .join(":");
+        let content = ImageIds::path()
+            .and_then(|path| fs::read_to_string(path).ok())
+            .unwrap_or_default();
+        let entries = content
+            .lines()
+            .filter_map(|line| {
+                let mut fields = line.split(' ');
+                let entry = (
+                    fields.next()?.to_string(),
+                    fields.next()?.to_string(),
+                    fields.next()?.parse().ok()?,
+                    fields.next()?.parse().ok()?,
+                    fields.next()?.parse().ok()?,
+                );
+                Some(entry)
+            })
+            .collect();
+
+        Self { terminal, entries }
+    }
+
+    /// Id and dimensions an image was sent to this terminal with
+    fn get(&self, key: &str) -> Option<(u32, u32, u32)> {
+        self.entries
+            .iter()
+            .rev()
+            .find(|(terminal, entry_key, ..)| *terminal == self.terminal && entry_key == key)
+            .map(|&(_, _, id, width, height)| (id, width, height))
+    }
+
+    /// Remember the id and dimensions an image was sent with, forgetting the oldest images
+    fn insert(&mut self, key: &str, id: u32, width: u32, height: u32) -> Result {
+        self.entries
+            .retain(|(terminal, entry_key, ..)| *terminal != self.terminal || entry_key != key);
+        self.entries
+            .push((self.terminal.clone(), key.to_string(), id, width, height));
+        let extra = self.entries.len().saturating_sub(MAX_IDS);
+        self.entries.drain(..extra);
+
+        let Some(path) = ImageIds::path() else {
+            return Ok(());
+        };
+        let dir = path.parent().unwrap_or(&path);
+        fs::create_dir_all(dir)?;
+        // Ids are written whole or not at all, even with concurrent previews
+        let mut file = tempfile::Builder::new().prefix(".").tempfile_in(dir)?;
+        for (terminal, key, id, width, height) in &self.entries {
+            writeln!(file, "{terminal} {key} {id} {width} {height}")?;
+        }
+        file.persist(path)?;
+        Ok(())
+    }
+}
+
+/// New id for an image, random and in the high range so that it doesn't collide
+/// with the ids of other programs, which usually count from 1
+fn new_id() -> u32 {
+    let mut hasher = RandomState::new().build_hasher();
+    SystemTime::now().hash(&mut hasher);
+    std::process::id().hash(&mut hasher);
+    (hasher.finish() as u32) | 0x8000_0000
+}
+
+/// Options the pixels sent for an image depend on, not where and how they're placed
+fn content_options(options: &Options) -> Options {
+    let mut options = options.clone();
+    (options.x, options.y) = (None, None);
+    (options.load, options.display, options.clear) = (None, None, None);
+    options.no_newline = false;
+    options
+}
+
 fn clear(stdout: &mut impl Write, id: u32, options: &Options) -> Result {
     if id == 0 {
         send_graphics_command(stdout, "a=d,d=a", None, !options.no_newline)
@@ -42,67 +158,150 @@ fn clear(stdout: &mut impl Write, id: u32, options: &Options) -> Result {
     }
 }
 
-fn load(stdout: &mut impl Write, id: u32, image_path: &PathBuf, options: &Options) -> Result {
-    let image = Reader::open(image_path)?
-        .with_guessed_format()?
-        .decode()?
-        .to_rgba8();
-    let (width, height) = image.dimensions();
-    let (mut tempfile, pathbuf) = create_temp_file(KITTY_PREFIX)?;
-    save_in_temp_file(image.as_raw(), &mut tempfile)?;
+/// Width, height and RGBA pixels of an image to send, from the cache if it has them
+fn pixels(image: &LoadedImage, options: &Options) -> Result<(u32, u32, Vec<u8>)> {
+    let term_size = TermSize::from_options(options)?;
+    let options = content_options(options);
+    let mut data = Vec::new();
+    write_cached(&mut data, image, "kitty", &options, |data| {
+        let image = image.decode(&term_size, &options)?.to_rgba8();
+        data.extend(image.width().to_le_bytes());
+        data.extend(image.height().to_le_bytes());
+        data.extend(image.as_raw());
+        Ok(())
+    })?;
+
+    let pixels = data.split_off(8);
+    let width = u32::from_le_bytes([data[0], data[1], data[2], data[3]]);
+    let height = u32::from_le_bytes([data[4], data[5], data[6], data[7]]);
+    Ok((width, height, pixels))
+}
+
+fn load(stdout: &mut impl Write, id: u32, image: &LoadedImage, options: &Options) -> Result {
+    let (width, height, pixels) = pixels(image, options)?;
+
+    // Id 0 picks the id of the image, the same every time it is loaded
+    let (id, ids) = if id == 0 {
+        let key = Cache::content_key(image, "kitty", &content_options(options))?;
+        let ids = ImageIds::open();
+        let id = ids.get(&key).map_or_else(new_id, |(id, ..)| id);
+        (id, Some((ids, key)))
+    } else {
+        (id, None)
+    };
 
     let command = format!("a=t,t=t,f=32,s={width},v={height},i={id},q=2");
-    send_graphics_command(stdout, &command, pathbuf.to_str(), !options.no_newline)
+    transmit(stdout, &command, &pixels, !options.no_newline)?;
+    if let Some((mut ids, key)) = ids {
+        // Remembering ids only saves retransmitting images later
+        let _ = ids.insert(&key, id, width, height);
+    }
+    Ok(())
 }
 
 fn display(
     stdout: &mut impl Write,
     id: Option<u32>,
-    image_path: &PathBuf,
+    image: &LoadedImage,
     options: &Options,
 ) -> Result {
-    let (mut tempfile, pathbuf) = create_temp_file(KITTY_PREFIX)?;
-    let (command, payload) = if let Some(id) = id {
-        let image_size = imagesize::size(image_path)?;
-        let (width, height) = (image_size.width as u32, image_size.height as u32);
-        let (cols, rows) =
-            fit_in_bounds(width, height, options.cols, options.rows, options.upscale)?;
-
-        let command = format!("a=p,c={cols},r={rows},i={id},q=2");
-        (command, None)
-    } else {
-        let image = Reader::open(image_path)?
-            .with_guessed_format()?
-            .decode()?
-            .to_rgba8();
-        let (width, height) = image.dimensions();
-        let (cols, rows) =
-            fit_in_bounds(width, height, options.cols, options.rows, options.upscale)?;
-        save_in_temp_file(image.as_raw(), &mut tempfile)?;
-
-        let command = format!("a=T,t=t,I=13,f=32,s={width},v={height},c={cols},r={rows},q=2",);
-        (command, pathbuf.to_str())
-    };
+    let term_size = TermSize::from_options(options)?;
+    let (width, height) = image.display_size(options)?;
+    let fit = layout::fit(&term_size, width, height, options)?;
+    let (cols, rows) = (fit.cols, fit.rows);
+
+    // Id 0 places the image loaded with it, or one sent by a previous run
+    if id == Some(0) {
+        move_cursor(stdout, options.x, options.y)?;
+        return display_reused(stdout, image, &fit, options);
+    }
 
     move_cursor(stdout, options.x, options.y)?;
-    send_graphics_command(stdout, &command, payload, !options.no_newline)?;
+    if let Some(id) = id {
+        // The shown part is given in pixels of the loaded image, which was decoded at
+        // about the size it takes (the same decode, usually read from the cache)
+        let source = match fit.crop {
+            Some(_) => {
+                let (width, height, _) = pixels(image, options)?;
+                source(&fit, width, height)
+            }
+            None => String::new(),
+        };
+        let command = format!("a=p,c={cols},r={rows},i={id}{source},q=2");
+        send_graphics_command(stdout, &command, None, !options.no_newline)
+    } else {
+        let (width, height, pixels) = pixels(image, options)?;
+        let command = format!(
+            "a=T,t=t,I=13,f=32,s={width},v={height},c={cols},r={rows}{},q=2",
+            source(&fit, width, height)
+        );
+        transmit(stdout, &command, &pixels, !options.no_newline)
+    }
+}
 
+/// Place an image the terminal still holds from a previous run, otherwise transmit it
+/// with an id that later runs reuse
+fn display_reused(
+    stdout: &mut impl Write,
+    image: &LoadedImage,
+    fit: &Fit,
+    options: &Options,
+) -> Result {
+    let (cols, rows) = (fit.cols, fit.rows);
+    let key = Cache::content_key(image, "kitty", &content_options(options))?;
+    let mut ids = ImageIds::open();
+
+    if let Some((id, width, height)) = ids.get(&key) {
+        // Errors are reported (q=1), the terminal answers ENOENT if it dropped the image
+        let command = format!(
+            "a=p,c={cols},r={rows},i={id}{},q=1",
+            source(fit, width, height)
+        );
+        send_graphics_command(stdout, &command, None, false)?;
+        let dropped = query_terminal(b"").is_ok_and(|response| response.contains("ENOENT"));
+        if !dropped {
+            if !options.no_newline {
+                stdout.write_all(b"\n")?;
+                stdout.flush()?;
+            }
+            return Ok(());
+        }
+    }
+
+    let (width, height, pixels) = pixels(image, options)?;
+    let id = ids.get(&key).map_or_else(new_id, |(id, ..)| id);
+
+    let command = format!(
+        "a=T,t=t,f=32,s={width},v={height},i={id},c={cols},r={rows}{},q=2",
+        source(fit, width, height)
+    );
+    transmit(stdout, &command, &pixels, !options.no_newline)?;
+    // Remembering ids only saves retransmitting images later
+    let _ = ids.insert(&key, id, width, height);
     Ok(())
 }
 
-pub fn preview(stdout: &mut impl Write, image_path: &PathBuf, options: &Options) -> Result {
+/// Source rectangle keys to only show the part of the image kept by the fit
+fn source(fit: &Fit, width: u32, height: u32) -> String {
+    match fit.crop_for(width, height) {
+        Some((x, y, w, h)) => format!(",x={x},y={y},w={w},h={h}"),
+        None => String::new(),
+    }
+}
+
+pub fn preview(stdout: &mut impl Write, image: &LoadedImage, options: &Options) -> Result {
     if let Some(id) = options.clear {
         clear(stdout, id, options)?;
     }
 
     match (options.load, options.display) {
         (Some(id_load), Some(id_display)) => {
-            load(stdout, id_load, image_path, options)?;
-            display(stdout, Some(id_display), image_path, options)?;
+            load(stdout, id_load, image, options)?;
+            display(stdout, Some(id_display), image, options)?;
         }
-        (Some(id), None) => load(stdout, id, image_path, options)?,
-        (None, Some(id)) => display(stdout, Some(id), image_path, options)?,
-        (None, None) => display(stdout, None, image_path, options)?,
+        (Some(id), None) => load(stdout, id, image, options)?,
+        (None, Some(id)) => display(stdout, Some(id), image, options)?,
+        (None, None) => display(stdout, None, image, options)?,
     }
     handle_spacing(stdout, options.spacing)?;
     Ok(())
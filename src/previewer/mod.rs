@@ -1,36 +1,333 @@
+use crate::cache::Cache;
+use crate::caption::{self, CaptionPosition};
+use crate::decode::LoadedImage;
+use crate::layout::{self, Area, VerticalAlign};
 use crate::options::Options;
-use crate::result::Result;
-use crate::support::Protocol;
+use crate::paths;
+use crate::result::{Error, Result};
+use crate::support::{Background, Protocol};
+use crate::utils::{
+    handle_spacing, move_cursor, move_cursor_column, move_cursor_down, move_cursor_up,
+    restore_cursor, save_cursor, TermSize,
+};
 use std::io::Write;
+use std::path::PathBuf;
 
 mod blocks;
 mod iterm;
 mod kitty;
 mod sixel;
+mod slideshow;
+mod viewer;
+
+/// Width of grid cells when neither the number of columns nor `--cols` is given
+const GRID_CELL_COLS: u32 = 20;
 
 /// Preview an image to stdout with the given options
 pub fn preview(stdout: &mut impl Write, options: &mut Options) -> Result {
+    if options.clear_cache {
+        Cache::clear()?;
+    }
+
     let protocol = Protocol::choose(options);
-    let image_paths = options.path.clone();
-    // If there is more than one path, render `-y` flag useless
-    // TODO: Does not work if the only path is a directory
-    if options.y.is_some() && image_paths.len() > 1 {
-        options.y = None;
-        // Notify about spacing flag
+    // Kitty handles transparency by itself, others need to know the background
+    if protocol != Protocol::Kitty {
+        options.background = Some(Background::choose(options));
+    }
+    let image_paths = paths::expand(options)?;
+    if options.view {
+        return viewer::view(stdout, protocol, &image_paths, options);
+    }
+    if let Some(seconds) = options.slideshow {
+        return slideshow::slideshow(stdout, protocol, &image_paths, seconds, options);
+    }
+
+    let mut failures = Vec::new();
+    if options.grid.is_some() {
+        preview_grid(stdout, protocol, &image_paths, options, &mut failures)?;
+    } else {
+        // If there is more than one image, render `-y` flag useless
+        if options.y.is_some() && image_paths.len() > 1 {
+            options.y = None;
+        }
+        // Same goes for vertical alignment
+        if image_paths.len() > 1 {
+            options.valign = None;
+            // Notify about spacing flag
+        }
+
+        for image_path in &image_paths {
+            let image = LoadedImage::new(image_path);
+            match preview_placed(stdout, protocol, &image, options) {
+                Err(err) if options.keep_going => {
+                    placeholder(stdout, &err, options, None)?;
+                    failures.push(err);
+                }
+                result => result?,
+            }
+        }
+    }
+
+    if failures.is_empty() {
+        Ok(())
+    } else {
+        Err(Error::Failed(failures))
+    }
+}
+
+/// Preview a single image with the chosen protocol
+fn preview_image(
+    stdout: &mut impl Write,
+    protocol: Protocol,
+    image: &LoadedImage,
+    options: &mut Options,
+) -> Result {
+    match protocol {
+        Protocol::Kitty => kitty::preview(stdout, image, options),
+        Protocol::Iterm => iterm::preview(stdout, image, options),
+        Protocol::Sixel => sixel::preview(stdout, image, options),
+        Protocol::Blocks => blocks::preview(stdout, image, options),
+    }
+    .map_err(|err| err.with_path(image.path()))
+}
+
+/// Preview a single image aligned and padded in the terminal, or in the box
+/// given by `-x`, `-y`, `--cols` and `--rows`
+fn preview_placed(
+    stdout: &mut impl Write,
+    protocol: Protocol,
+    image: &LoadedImage,
+    options: &mut Options,
+) -> Result {
+    if !layout::is_placed(options) {
+        return preview_captioned(stdout, protocol, image, options);
+    }
+
+    let term_size = TermSize::from_options(options)?;
+    let caption_rows = u32::from(options.caption.is_some());
+    let (x, y) = (options.x.unwrap_or(0), options.y.unwrap_or(0));
+    let area = Area {
+        x,
+        y,
+        cols: options.cols.unwrap_or(term_size.cols.saturating_sub(x)),
+        rows: options
+            .rows
+            .unwrap_or(term_size.rows.saturating_sub(y + 1))
+            .saturating_sub(caption_rows),
+    };
+    let placed = layout::place(image, options, area).map_err(|err| err.with_path(image.path()))?;
+
+    let mut image_options = options.clone();
+    image_options.set_size(Some(placed.cols), Some(placed.rows));
+    // Previews follow the output, unless they have to be placed vertically
+    if options.y.is_some()
+        || options
+            .valign
+            .is_some_and(|valign| valign != VerticalAlign::Top)
+    {
+        image_options.set_position(Some(placed.x), Some(placed.y));
+        preview_captioned(stdout, protocol, image, &mut image_options)
+    } else {
+        let padding = options.padding.unwrap_or_default();
+        image_options.set_position(Some(placed.x), None);
+        image_options.set_spacing(Some(padding.bottom + options.spacing.unwrap_or(0)));
+        handle_spacing(stdout, Some(padding.top))?;
+        preview_captioned(stdout, protocol, image, &mut image_options)
+    }
+}
+
+/// Preview a single image with its caption, if any
+fn preview_captioned(
+    stdout: &mut impl Write,
+    protocol: Protocol,
+    image: &LoadedImage,
+    options: &mut Options,
+) -> Result {
+    let Some(text) = caption_text(image, options, None)? else {
+        return preview_image(stdout, protocol, image, options);
+    };
+
+    let mut image_options = options.clone();
+    match options.caption_position.unwrap_or_default() {
+        CaptionPosition::Above => {
+            move_cursor(stdout, options.x, options.y)?;
+            stdout.write_all(text.as_bytes())?;
+            stdout.write_all(b"\n")?;
+            image_options.set_position(options.x, options.y.map(|y| y + 1));
+            preview_image(stdout, protocol, image, &mut image_options)
+        }
+        CaptionPosition::Below => {
+            // Spacing goes after the caption, not between it and the image
+            image_options.set_spacing(None);
+            preview_image(stdout, protocol, image, &mut image_options)?;
+            if options.no_newline {
+                stdout.write_all(b"\n")?;
+            }
+            move_cursor(stdout, options.x, None)?;
+            stdout.write_all(text.as_bytes())?;
+            if !options.no_newline {
+                stdout.write_all(b"\n")?;
+            }
+            stdout.flush()?;
+            handle_spacing(stdout, options.spacing)
+        }
     }
+}
 
-    for image_path in &image_paths {
-        if image_path.is_dir() {
-            continue;
+/// Caption of an image truncated to the given number of cols (the image width by default)
+fn caption_text(
+    image: &LoadedImage,
+    options: &Options,
+    cols: Option<u32>,
+) -> Result<Option<String>> {
+    let Some(template) = &options.caption else {
+        return Ok(None);
+    };
+
+    let text = caption::caption(image, template).map_err(|err| err.with_path(image.path()))?;
+    let cols = match cols {
+        Some(cols) => cols,
+        None => {
+            let (cols, _) =
+                layout::fitted_size(image, options).map_err(|err| err.with_path(image.path()))?;
+            cols
         }
+    };
+    Ok(Some(caption::truncate(&text, cols)))
+}
+
+/// Tile images in a grid, each one fitted in its own cell
+fn preview_grid(
+    stdout: &mut impl Write,
+    protocol: Protocol,
+    image_paths: &[PathBuf],
+    options: &Options,
+    failures: &mut Vec<Error>,
+) -> Result {
+    let term_size = TermSize::from_options(options)?;
+    let (col_size, row_size) = term_size.cell_size();
+
+    let gap = options.spacing.unwrap_or(1);
+    let x = options.x.unwrap_or(0);
+    let width = term_size.cols.saturating_sub(x).max(1);
+    let columns = match options.grid {
+        Some(0) | None => (width + gap) / (options.cols.unwrap_or(GRID_CELL_COLS) + gap),
+        Some(columns) => columns,
+    }
+    .max(1);
+    let cell_cols = options
+        .cols
+        .unwrap_or(((width + gap) / columns).saturating_sub(gap))
+        .max(1);
+    let caption_rows = u32::from(options.caption.is_some());
+    // Cells are square unless told otherwise
+    let cell_rows = options
+        .rows
+        .unwrap_or(cell_cols * col_size / row_size)
+        .clamp(1, term_size.rows.saturating_sub(1).max(1))
+        .max(caption_rows + 1);
+    let image_rows = cell_rows - caption_rows;
+    let caption_position = options.caption_position.unwrap_or_default();
+
+    // Each image is drawn inside its cell, without moving on by itself
+    let mut cell = options.clone();
+    cell.set_spacing(None);
+    cell.no_newline();
+    cell.set_static();
 
-        match protocol {
-            Protocol::Kitty => kitty::preview(stdout, image_path, options)?,
-            Protocol::Iterm => iterm::preview(stdout, image_path, options)?,
-            Protocol::Sixel => sixel::preview(stdout, image_path, options)?,
-            Protocol::Blocks => blocks::preview(stdout, image_path, options)?,
+    move_cursor(stdout, None, options.y)?;
+    for (r, row) in image_paths.chunks(columns as usize).enumerate() {
+        if r != 0 {
+            handle_spacing(stdout, Some(gap))?;
         }
+        // Make room for the whole row (and the line some protocols move to after
+        // an image), so that the terminal doesn't scroll while drawing it
+        stdout.write_all(&b"\n".repeat(cell_rows as usize + 1))?;
+        move_cursor_up(stdout, cell_rows)?;
+        save_cursor(stdout)?;
+
+        for (c, image_path) in row.iter().enumerate() {
+            let image = LoadedImage::new(image_path);
+            let cell_x = x + c as u32 * (cell_cols + gap);
+            let area = Area {
+                x: cell_x,
+                y: 0,
+                cols: cell_cols,
+                rows: image_rows,
+            };
+
+            let result = caption_text(&image, options, Some(cell_cols))
+                .and_then(|text| {
+                    // Images are aligned and padded in their cell, below the caption if any
+                    let placed = if layout::is_placed(options) {
+                        layout::place(&image, options, area)?
+                    } else {
+                        area
+                    };
+                    cell.set_position(Some(placed.x), None);
+                    cell.set_size(Some(placed.cols), Some(placed.rows));
+
+                    let above = text.is_some() && caption_position == CaptionPosition::Above;
+                    restore_cursor(stdout)?;
+                    if placed.y + u32::from(above) > 0 {
+                        move_cursor_down(stdout, placed.y + u32::from(above) - 1)?;
+                    }
+                    preview_image(stdout, protocol, &image, &mut cell)?;
+                    Ok(text)
+                })
+                .map_err(|err| err.with_path(image_path));
+
+            match result {
+                Ok(Some(text)) => {
+                    // Captions are on the first or last line of the cell, aligned across the row
+                    restore_cursor(stdout)?;
+                    if caption_position == CaptionPosition::Below {
+                        move_cursor_down(stdout, image_rows - 1)?;
+                    }
+                    move_cursor_column(stdout, cell_x)?;
+                    stdout.write_all(text.as_bytes())?;
+                }
+                Ok(None) => {}
+                Err(err) if options.keep_going => {
+                    restore_cursor(stdout)?;
+                    placeholder(stdout, &err, &cell, Some(cell_cols))?;
+                    failures.push(err);
+                }
+                Err(err) => return Err(err),
+            }
+        }
+
+        restore_cursor(stdout)?;
+        let last_row = (r + 1) * columns as usize >= image_paths.len();
+        let down = if last_row && options.no_newline {
+            cell_rows - 1
+        } else {
+            cell_rows
+        };
+        stdout.write_all(&b"\n".repeat(down as usize))?;
+        stdout.flush()?;
     }
 
     Ok(())
 }
+
+/// Show which image failed and why, in place of its preview
+fn placeholder(
+    stdout: &mut impl Write,
+    err: &Error,
+    options: &Options,
+    width: Option<u32>,
+) -> Result {
+    let mut text = format!("[{err}]");
+    if let Some(width) = width {
+        text = text.chars().take(width as usize).collect();
+    }
+
+    move_cursor(stdout, options.x, options.y)?;
+    stdout.write_all(text.as_bytes())?;
+    if !options.no_newline {
+        stdout.write_all(b"\n")?;
+    }
+    stdout.flush()?;
+    handle_spacing(stdout, options.spacing)
+}
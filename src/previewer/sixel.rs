@@ -1,40 +1,201 @@
+use crate::cache::write_cached;
+use crate::decode::{
+    animation_frames, check_limits, fitted_frame, is_animated, limits, needs_conversion,
+    LoadedImage,
+};
+use crate::layout;
 use crate::options::Options;
+use crate::resize::{resize, Filter};
 use crate::result::Result;
-use crate::utils::{fit_in_bounds, handle_spacing, move_cursor, TermSize};
+use crate::support::Background;
+use crate::transform::is_transformed;
+use crate::utils::{
+    composite, create_temp_file, handle_spacing, move_cursor, play_animation, restore_cursor,
+    save_cursor, TermSize, SIXEL_PREFIX,
+};
+use image::{DynamicImage, ImageFormat, ImageOutputFormat};
 use sixel_rs::encoder::Encoder;
 use sixel_rs::optflags::{EncodePolicy, ResampleMethod, SizeSpecification::Pixel};
 use std::io::Write;
-use std::path::PathBuf;
+use std::time::Duration;
 
-pub fn display(stdout: &mut impl Write, image_path: &PathBuf, options: &Options) -> Result {
-    let image_size = imagesize::size(image_path)?;
-    let (width, height) = (image_size.width as u32, image_size.height as u32);
-    let (cols, rows) = fit_in_bounds(width, height, options.cols, options.rows, options.upscale)?;
+pub fn display(stdout: &mut impl Write, image: &LoadedImage, options: &Options) -> Result {
+    let (width, height) = image.display_size(options)?;
+    let term_size = TermSize::from_options(options)?;
+    let fit = layout::fit(&term_size, width, height, options)?;
 
-    let term_size = TermSize::from_ioctl()?;
-    let (col_size, row_size) = match term_size.get_cell_size() {
-        Some((0, 0)) | None => (15, 30),
-        Some((c, r)) => (c, r),
+    let (col_size, row_size) = term_size.cell_size();
+    let (shown_width, shown_height) = (
+        u64::from(fit.cols * col_size),
+        u64::from(fit.rows * row_size),
+    );
+
+    // libsixel has no box filter and only resizes sRGB values, we resize ourselves then
+    let resampled = options.linear || options.filter == Some(Filter::Box);
+    let finish = |image: DynamicImage| {
+        if resampled {
+            let filter = options.filter.unwrap_or(Filter::Nearest);
+            let (width, height) = (shown_width as u32, shown_height as u32);
+            resize(&fit.crop(&image), width, height, filter, options.linear)
+        } else {
+            image
+        }
     };
 
     let encoder = Encoder::new()?;
-    encoder.set_width(Pixel(u64::from(cols * col_size)))?;
-    encoder.set_height(Pixel(u64::from(rows * row_size)))?;
-    encoder.set_resampling(ResampleMethod::Nearest)?;
+    // libsixel crops after resizing, so the whole image is scaled for the
+    // shown part of it to take the cols and rows
+    match fit.crop {
+        Some((x, y, w, h)) if !resampled => {
+            let scale_x = |value: u32| u64::from(value) * shown_width / u64::from(w);
+            let scale_y = |value: u32| u64::from(value) * shown_height / u64::from(h);
+            encoder.set_width(Pixel(scale_x(width)))?;
+            encoder.set_height(Pixel(scale_y(height)))?;
+            encoder.set_crop(
+                shown_width as i64,
+                shown_height as i64,
+                scale_x(x) as i64,
+                scale_y(y) as i64,
+            )?;
+        }
+        _ => {
+            encoder.set_width(Pixel(shown_width))?;
+            encoder.set_height(Pixel(shown_height))?;
+        }
+    }
+    encoder.set_resampling(match options.filter {
+        Some(Filter::Triangle) => ResampleMethod::Bilinear,
+        Some(Filter::CatmullRom) => ResampleMethod::Bicubic,
+        Some(Filter::Gaussian) => ResampleMethod::Gaussian,
+        Some(Filter::Lanczos3) => ResampleMethod::Lanczos3,
+        _ => ResampleMethod::Nearest,
+    })?;
     encoder.set_encode_policy(EncodePolicy::Fast)?;
     if options.gif_static {
         encoder.use_static()?;
     };
 
     move_cursor(stdout, options.x, options.y)?;
-    encoder.encode_file(image_path)?;
+    if let Some(frames) = animation(image, &term_size, resampled, options)? {
+        let background = Background::choose(options);
+        play_animation(
+            stdout,
+            &frames,
+            options.gif_loop,
+            |stdout, frame, first_frame| {
+                // Every frame is drawn where the first one was
+                if first_frame {
+                    save_cursor(stdout)?;
+                } else {
+                    restore_cursor(stdout)?;
+                }
+                let frame = composite(frame, background, options.alpha_threshold).into();
+                encode_image(&encoder, &finish(frame))
+            },
+        )?;
+    } else {
+        let encode = || match decoded(image, &term_size, resampled, options)? {
+            Some(decoded) => encode_image(&encoder, &finish(decoded)),
+            None => {
+                check_limits(image.size()?, options)?;
+                Ok(encoder.encode_file(image.path())?)
+            }
+        };
+        // libsixel plays GIFs by itself while writing them, they can't be cached
+        if options.gif_static || image.format()? != Some(ImageFormat::Gif) {
+            write_cached(stdout, image, "sixel", options, |stdout| {
+                let output = create_temp_file(SIXEL_PREFIX, ".six")?;
+                encoder.set_output(output.path())?;
+                encode()?;
+                stdout.write_all(&std::fs::read(output.path())?)?;
+                Ok(())
+            })?;
+        } else {
+            encode()?;
+        }
+    }
     stdout.flush()?;
 
     Ok(())
 }
 
-pub fn preview(stdout: &mut impl Write, image_path: &PathBuf, options: &Options) -> Result {
-    display(stdout, image_path, options)?;
+/// Encode a decoded image, through a temporary file as libsixel frees the
+/// pixels given to `encode_bytes`, which are then freed again on our side
+fn encode_image(encoder: &Encoder, image: &DynamicImage) -> Result {
+    let mut file = create_temp_file(SIXEL_PREFIX, ".png")?;
+    DynamicImage::ImageRgb8(image.to_rgb8())
+        .write_to(file.as_file_mut(), ImageOutputFormat::Png)?;
+    file.as_file_mut().flush()?;
+    encoder.encode_file(file.path())?;
+    Ok(())
+}
+
+/// Frames of APNG and animated WebP images (and transformed or resampled
+/// GIFs), libsixel only animates GIFs by itself
+fn animation(
+    image: &LoadedImage,
+    term_size: &TermSize,
+    resampled: bool,
+    options: &Options,
+) -> Result<Option<Vec<(Duration, DynamicImage)>>> {
+    let converted = match image.format()? {
+        Some(ImageFormat::Png | ImageFormat::WebP) => true,
+        Some(ImageFormat::Gif) => resampled || is_transformed(options),
+        _ => false,
+    };
+    if options.gif_static || !converted || !is_animated(image.buffer()?) {
+        return Ok(None);
+    }
+
+    // libsixel resizes frames to absolute sizes, they can be downscaled beforehand
+    animation_frames(image.buffer()?, limits(options), |frame| {
+        fitted_frame(frame, term_size, options)
+    })
+}
+
+/// Decode images libsixel can't display as is (SVGs, transparent, oriented,
+/// transformed, resampled or with an ICC profile), composited over the background
+fn decoded(
+    image: &LoadedImage,
+    term_size: &TermSize,
+    resampled: bool,
+    options: &Options,
+) -> Result<Option<DynamicImage>> {
+    let background = Background::choose(options);
+    #[cfg(feature = "svg")]
+    if image.is_svg()? {
+        let decoded = image.decode(term_size, options)?;
+        return Ok(Some(
+            composite(decoded, background, options.alpha_threshold).into(),
+        ));
+    }
+
+    let edited = resampled || is_transformed(options);
+    let format = image.format()?;
+    match format {
+        Some(ImageFormat::Gif) if !edited => return Ok(None),
+        None => return Ok(None),
+        _ => {}
+    }
+
+    let converted = needs_conversion(image.buffer()?);
+    let transparent = format != Some(ImageFormat::Jpeg) && background != Background::None;
+    if !converted && !transparent && !edited {
+        return Ok(None);
+    }
+
+    let decoded = image.decode(term_size, options)?;
+    if converted || edited || decoded.color().has_alpha() {
+        Ok(Some(
+            composite(decoded, background, options.alpha_threshold).into(),
+        ))
+    } else {
+        Ok(None)
+    }
+}
+
+pub fn preview(stdout: &mut impl Write, image: &LoadedImage, options: &Options) -> Result {
+    display(stdout, image, options)?;
     handle_spacing(stdout, options.spacing)?;
     Ok(())
 }
@@ -0,0 +1,88 @@
+use super::viewer::{clear_images, draw, screen_options};
+use crate::decode::LoadedImage;
+use crate::options::Options;
+use crate::result::Result;
+use crate::support::Protocol;
+use crate::utils::{
+    enter_alternate_screen, hide_cursor, leave_alternate_screen, open_tty, show_cursor,
+    CtrlcHandler, RawMode,
+};
+use crossbeam_channel::select;
+use std::collections::hash_map::RandomState;
+use std::hash::BuildHasher;
+use std::io::Write;
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// Show images full-screen one after another, each for the given number of seconds,
+/// until the last one (forever if looping) or CTRL-C
+pub fn slideshow(
+    stdout: &mut impl Write,
+    protocol: Protocol,
+    image_paths: &[PathBuf],
+    seconds: f64,
+    options: &Options,
+) -> Result {
+    if image_paths.is_empty() {
+        return Ok(());
+    }
+
+    let handler = CtrlcHandler::new()?;
+    // Keys typed during the slideshow aren't echoed over the images, CTRL-C still
+    // interrupts it (without a terminal to read from, there are no keys to hide)
+    let mode = open_tty().and_then(|tty| RawMode::new(&tty, true)).ok();
+    enter_alternate_screen(stdout)?;
+    hide_cursor(stdout)?;
+
+    let mut paths: Vec<&PathBuf> = image_paths.iter().collect();
+    let delay = Duration::from_secs_f64(seconds);
+    let mut image_options = screen_options(protocol, options);
+    let mut interrupted = false;
+
+    // The terminal is restored even if drawing fails
+    let result = (|| -> Result {
+        'slideshow: loop {
+            if options.shuffle {
+                shuffle(&mut paths);
+            }
+            for path in &paths {
+                let image = LoadedImage::new(path);
+                draw(stdout, protocol, &image, &mut image_options, None)?;
+                select! {
+                    default(delay) => {},
+                    recv(handler.receiver) -> _ => {
+                        interrupted = true;
+                        break 'slideshow;
+                    }
+                }
+            }
+
+            if !options.gif_loop {
+                break 'slideshow;
+            }
+        }
+        Ok(())
+    })();
+
+    clear_images(stdout, protocol)?;
+    show_cursor(stdout)?;
+    leave_alternate_screen(stdout)?;
+    drop(mode);
+    // The CTRL-C handler waits for the terminal to be restored, then exits
+    if interrupted {
+        handler.sender.send(true)?;
+        loop {
+            std::thread::park();
+        }
+    }
+    result
+}
+
+/// Put paths in a random order (Fisher-Yates)
+fn shuffle(paths: &mut [&PathBuf]) {
+    let state = RandomState::new();
+    for i in (1..paths.len()).rev() {
+        let j = (state.hash_one(i) % (i as u64 + 1)) as usize;
+        paths.swap(i, j);
+    }
+}
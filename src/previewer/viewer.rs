@@ -0,0 +1,307 @@
+use super::{placeholder, preview_placed};
+use crate::caption;
+use crate::decode::LoadedImage;
+use crate::layout::{self, FitMode, HorizontalAlign, VerticalAlign};
+use crate::options::Options;
+use crate::result::{Error, Result};
+use crate::support::Protocol;
+use crate::transform::Zoom;
+use crate::utils::{
+    enter_alternate_screen, hide_cursor, leave_alternate_screen, move_cursor, open_tty, poll_tty,
+    show_cursor, CtrlcHandler, RawMode, TermSize,
+};
+use clap::ValueEnum;
+use console::{Key, Term};
+use std::fs::File;
+use std::io::{IsTerminal, Write};
+use std::path::PathBuf;
+
+/// How often the terminal size is checked while waiting for a key, in milliseconds
+const RESIZE_POLL: i32 = 200;
+/// Zoom factor applied by each key press, and the largest one
+const ZOOM_STEP: f64 = 1.5;
+const MAX_ZOOM: f64 = 64.0;
+const KEYS: &str =
+    "n/p: next/previous  +/-: zoom  hjkl: pan  0: reset  f: fit  u: upscale  i: info  q: quit";
+
+/// Alternate screen the viewer draws in, the terminal is restored when it is dropped
+struct Screen {
+    tty: File,
+    _mode: RawMode,
+}
+
+impl Screen {
+    /// Switch to the alternate screen, with keys read as soon as they're pressed
+    fn open() -> Result<Self> {
+        let mut tty = open_tty()?;
+        let mode = RawMode::new(&tty, false)?;
+
+        enter_alternate_screen(&mut tty)?;
+        hide_cursor(&mut tty)?;
+        Ok(Self { tty, _mode: mode })
+    }
+
+    /// Wait for a key press, false if none came before the timeout
+    fn poll(&self, timeout: i32) -> bool {
+        poll_tty(&self.tty, timeout)
+    }
+}
+
+impl Drop for Screen {
+    fn drop(&mut self) {
+        let _ = show_cursor(&mut self.tty);
+        let _ = leave_alternate_screen(&mut self.tty);
+    }
+}
+
+/// What the viewer shows, changed by key presses
+struct View {
+    index: usize,
+    zoom: f64,
+    center: (f64, f64),
+    fit: FitMode,
+    scale: Option<u32>,
+    upscale: bool,
+    info: bool,
+}
+
+impl View {
+    fn new(options: &Options) -> Self {
+        let zoom = options.zoom.unwrap_or(Zoom {
+            factor: 1.0,
+            center: (50.0, 50.0),
+        });
+
+        Self {
+            index: 0,
+            zoom: zoom.factor,
+            center: zoom.center,
+            fit: layout::fit_mode(options),
+            scale: options.scale,
+            upscale: options.upscale,
+            info: false,
+        }
+    }
+
+    /// Show another image, as a whole
+    fn go_to(&mut self, index: usize) {
+        self.index = index;
+        self.reset_zoom();
+    }
+
+    fn reset_zoom(&mut self) {
+        self.zoom = 1.0;
+        self.center = (50.0, 50.0);
+    }
+
+    fn zoom_by(&mut self, factor: f64) {
+        self.zoom = (self.zoom * factor).clamp(1.0, MAX_ZOOM);
+        if self.zoom == 1.0 {
+            self.reset_zoom();
+        }
+    }
+
+    /// Move the centre of the zoomed region by a quarter of what is shown
+    fn pan(&mut self, x: f64, y: f64) {
+        let step = 25.0 / self.zoom;
+        self.center = (
+            (self.center.0 + x * step).clamp(0.0, 100.0),
+            (self.center.1 + y * step).clamp(0.0, 100.0),
+        );
+    }
+
+    /// Go to the next fit mode, dropping the scale
+    fn cycle_fit(&mut self) {
+        let modes = FitMode::value_variants();
+        let current = modes.iter().position(|mode| *mode == self.fit);
+        self.fit = modes[current.map_or(0, |i| (i + 1) % modes.len())];
+        self.scale = None;
+    }
+
+    /// Options to preview the current image with
+    fn options(&self, protocol: Protocol, options: &Options) -> Options {
+        let mut options = screen_options(protocol, options);
+        options.set_fit(Some(self.fit), self.scale);
+        let zoom = (self.zoom > 1.0).then_some(Zoom {
+            factor: self.zoom,
+            center: self.center,
+        });
+        options.set_transform(options.crop, zoom, options.rotate, options.flip);
+        options.upscale = self.upscale;
+        options
+    }
+
+    /// Line describing the current image and the keys
+    fn info(&self, image: &LoadedImage, count: usize) -> String {
+        let caption =
+            caption::caption(image, "full").unwrap_or_else(|_| image.path().display().to_string());
+        let fit = self
+            .fit
+            .to_possible_value()
+            .map(|value| value.get_name().to_string())
+            .unwrap_or_default();
+
+        format!(
+            "{}/{count}  {caption}  zoom {:.1}x  fit {fit}  |  {KEYS}",
+            self.index + 1,
+            self.zoom
+        )
+    }
+}
+
+/// Browse images full-screen until quit, redrawing them when the terminal is resized
+pub fn view(
+    stdout: &mut impl Write,
+    protocol: Protocol,
+    image_paths: &[PathBuf],
+    options: &Options,
+) -> Result {
+    if image_paths.is_empty() {
+        return Ok(());
+    }
+    // Images would be drawn away from the screen set up on the terminal
+    if !std::io::stdout().is_terminal() {
+        return Err(Error::NotTerminal);
+    }
+
+    // CTRL-C is read as a key, interrupts from other processes wait for the terminal
+    // to be restored
+    let handler = CtrlcHandler::new()?;
+    let screen = Screen::open()?;
+    let term = Term::read_write_pair(screen.tty.try_clone()?, screen.tty.try_clone()?);
+    let mut view = View::new(options);
+    let mut image = LoadedImage::new(&image_paths[0]);
+    let mut geometry = None;
+    let mut redraw = true;
+    let mut interrupted = false;
+
+    loop {
+        if handler.receiver.try_recv().is_ok() {
+            interrupted = true;
+            break;
+        }
+        let term_size = TermSize::from_options(options)?;
+        let current = (
+            term_size.cols,
+            term_size.rows,
+            term_size.width,
+            term_size.height,
+        );
+        if geometry != Some(current) {
+            geometry = Some(current);
+            redraw = true;
+        }
+        if redraw {
+            // Pixels are decoded for a terminal size and options, which have changed
+            image.forget_decoded();
+            let info = view.info.then(|| view.info(&image, image_paths.len()));
+            let mut image_options = view.options(protocol, options);
+            draw(
+                stdout,
+                protocol,
+                &image,
+                &mut image_options,
+                info.as_deref(),
+            )?;
+            redraw = false;
+        }
+        if !screen.poll(RESIZE_POLL) {
+            continue;
+        }
+
+        let last = image_paths.len() - 1;
+        let index = view.index;
+        match term.read_key_raw() {
+            Ok(Key::Char('q') | Key::CtrlC | Key::Escape) => break,
+            Err(err) => return Err(err.into()),
+            Ok(Key::ArrowRight | Key::PageDown | Key::Char('n' | ' ')) => {
+                view.go_to((index + 1).min(last));
+            }
+            Ok(Key::ArrowLeft | Key::PageUp | Key::Backspace | Key::Char('p')) => {
+                view.go_to(index.saturating_sub(1));
+            }
+            Ok(Key::Home | Key::Char('g')) => view.go_to(0),
+            Ok(Key::End | Key::Char('G')) => view.go_to(last),
+            Ok(Key::Char('+' | '=')) => view.zoom_by(ZOOM_STEP),
+            Ok(Key::Char('-')) => view.zoom_by(1.0 / ZOOM_STEP),
+            Ok(Key::Char('0')) => view.reset_zoom(),
+            Ok(Key::Char('h')) => view.pan(-1.0, 0.0),
+            Ok(Key::Char('l')) => view.pan(1.0, 0.0),
+            Ok(Key::ArrowUp | Key::Char('k')) => view.pan(0.0, -1.0),
+            Ok(Key::ArrowDown | Key::Char('j')) => view.pan(0.0, 1.0),
+            Ok(Key::Char('f')) => view.cycle_fit(),
+            Ok(Key::Char('u')) => view.upscale = !view.upscale,
+            Ok(Key::Char('i')) => view.info = !view.info,
+            Ok(_) => continue,
+        }
+
+        if view.index != index {
+            image = LoadedImage::new(&image_paths[view.index]);
+        }
+        redraw = true;
+    }
+
+    clear_images(stdout, protocol)?;
+    drop(screen);
+    // The CTRL-C handler waits for the terminal to be restored, then exits
+    if interrupted {
+        handler.sender.send(true)?;
+        loop {
+            std::thread::park();
+        }
+    }
+    Ok(())
+}
+
+/// Options to preview images with, centred in the whole screen
+pub(super) fn screen_options(protocol: Protocol, options: &Options) -> Options {
+    let mut options = options.clone();
+    options.set_position(Some(0), Some(0));
+    options.set_size(None, None);
+    options.set_spacing(None);
+    options.set_alignment(
+        Some(options.align.unwrap_or(HorizontalAlign::Center)),
+        Some(options.valign.unwrap_or(VerticalAlign::Middle)),
+        options.padding,
+    );
+    options.no_newline();
+    // Animations would hold the screen until they end
+    options.set_static();
+    // Kitty images stay on screen until deleted
+    options.clear = (protocol == Protocol::Kitty).then_some(0);
+    (options.load, options.display) = (None, None);
+    options
+}
+
+/// Clear the screen and draw an image, with an info line at the bottom if given
+pub(super) fn draw(
+    stdout: &mut impl Write,
+    protocol: Protocol,
+    image: &LoadedImage,
+    options: &mut Options,
+    info: Option<&str>,
+) -> Result {
+    stdout.write_all(b"\x1b[2J")?;
+    let term_size = TermSize::from_options(options)?;
+    if let Err(err) = preview_placed(stdout, protocol, image, options) {
+        placeholder(stdout, &err, options, Some(term_size.cols))?;
+    }
+
+    // The last row is left free by previews
+    if let Some(info) = info {
+        let info = caption::truncate(info, term_size.cols);
+        move_cursor(stdout, Some(0), Some(term_size.rows.saturating_sub(1)))?;
+        stdout.write_all(format!("\x1b[7m{info}\x1b[m").as_bytes())?;
+    }
+    stdout.flush()?;
+    Ok(())
+}
+
+/// Delete the images kitty keeps on screen
+pub(super) fn clear_images(stdout: &mut impl Write, protocol: Protocol) -> Result {
+    if protocol == Protocol::Kitty {
+        stdout.write_all(b"\x1b_Ga=d,d=a\x1b\\")?;
+        stdout.flush()?;
+    }
+    Ok(())
+}
@@ -0,0 +1,99 @@
+use clap::ValueEnum;
+use fast_image_resize::images::{Image, ImageRef};
+use fast_image_resize::{
+    create_srgb_mapper, FilterType, PixelType, ResizeAlg, ResizeOptions, Resizer,
+};
+use image::{DynamicImage, RgbImage, RgbaImage};
+use serde::Deserialize;
+use std::borrow::Cow;
+
+/// Resampling filter used to resize images
+/// (box averages all the pixels covered, which suits heavy downscaling)
+#[derive(Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, ValueEnum, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Filter {
+    Nearest,
+    Triangle,
+    CatmullRom,
+    Gaussian,
+    Lanczos3,
+    Box,
+}
+
+impl Filter {
+    /// Matching algorithm of fast_image_resize
+    fn resize_alg(self) -> ResizeAlg {
+        match self {
+            Filter::Nearest => ResizeAlg::Nearest,
+            Filter::Triangle => ResizeAlg::Convolution(FilterType::Bilinear),
+            Filter::CatmullRom => ResizeAlg::Convolution(FilterType::CatmullRom),
+            Filter::Gaussian => ResizeAlg::Convolution(FilterType::Gaussian),
+            Filter::Lanczos3 => ResizeAlg::Convolution(FilterType::Lanczos3),
+            Filter::Box => ResizeAlg::Convolution(FilterType::Box),
+        }
+    }
+}
+
+/// Resize an image to exactly the given size, optionally in linear light
+/// (sRGB values are not proportional to light, averaging them darkens details)
+///
+/// Uses SIMD-accelerated convolution, transparent pixels are premultiplied so they don't bleed
+pub fn resize(
+    image: &DynamicImage,
+    width: u32,
+    height: u32,
+    filter: Filter,
+    linear: bool,
+) -> DynamicImage {
+    let (width, height) = (width.max(1), height.max(1));
+    if image.width() == 0 || image.height() == 0 {
+        return DynamicImage::new_rgba8(width, height);
+    }
+
+    // Avoid copying the pixels of large images when they're already 8 bits
+    let alpha = image.color().has_alpha();
+    let pixels = match image {
+        DynamicImage::ImageRgba8(_) | DynamicImage::ImageRgb8(_) => Cow::Borrowed(image.as_bytes()),
+        _ if alpha => Cow::Owned(image.to_rgba8().into_raw()),
+        _ => Cow::Owned(image.to_rgb8().into_raw()),
+    };
+    let (pixel_type, linear_type) = if alpha {
+        (PixelType::U8x4, PixelType::U16x4)
+    } else {
+        (PixelType::U8x3, PixelType::U16x3)
+    };
+    let source = ImageRef::new(image.width(), image.height(), &pixels, pixel_type)
+        .expect("buffer matches image size");
+    let mut destination = Image::new(width, height, pixel_type);
+
+    let mut resizer = Resizer::new();
+    let options = ResizeOptions::new().resize_alg(filter.resize_alg());
+    if linear {
+        // 16 bits are needed to keep dark tones once linear
+        let mapper = create_srgb_mapper();
+        let mut source_linear = Image::new(image.width(), image.height(), linear_type);
+        let mut destination_linear = Image::new(width, height, linear_type);
+        mapper
+            .forward_map(&source, &mut source_linear)
+            .expect("pixel types can be mapped");
+        resizer
+            .resize(&source_linear, &mut destination_linear, &options)
+            .expect("images have the same pixel type");
+        mapper
+            .backward_map(&destination_linear, &mut destination)
+            .expect("pixel types can be mapped");
+    } else {
+        resizer
+            .resize(&source, &mut destination, &options)
+            .expect("images have the same pixel type");
+    }
+
+    let pixels = destination.into_vec();
+    if alpha {
+        let image = RgbaImage::from_raw(width, height, pixels).expect("buffer matches image size");
+        DynamicImage::ImageRgba8(image)
+    } else {
+        let image = RgbImage::from_raw(width, height, pixels).expect("buffer matches image size");
+        DynamicImage::ImageRgb8(image)
+    }
+}
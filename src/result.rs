@@ -1,9 +1,55 @@
+use std::path::{Path, PathBuf};
+
+/// Broad category of an error, which determines the exit code
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorKind {
+    /// File doesn't exist
+    NotFound,
+    /// File isn't in a supported format
+    Unsupported,
+    /// File is corrupted or couldn't be decoded
+    Decode,
+    /// Terminal is missing or can't display images
+    Terminal,
+    /// Config file or option is invalid
+    Config,
+    /// Some images couldn't be previewed (keep going mode)
+    Partial,
+    /// Anything else
+    Other,
+}
+
+impl ErrorKind {
+    /// Exit code documented for this kind of error
+    pub fn exit_code(self) -> i32 {
+        match self {
+            ErrorKind::Other => 1,
+            ErrorKind::NotFound => 3,
+            ErrorKind::Unsupported => 4,
+            ErrorKind::Decode => 5,
+            ErrorKind::Terminal => 6,
+            ErrorKind::Config => 7,
+            ErrorKind::Partial => 8,
+        }
+    }
+}
+
 #[derive(Debug)]
 pub enum Error {
+    /// Error that happened while handling a file
+    File(PathBuf, Box<Error>),
+    /// Some images couldn't be previewed (keep going mode)
+    Failed(Vec<Error>),
     /// Input/Output error
     Io(std::io::Error),
+    /// No controlling terminal
+    Tty(std::io::Error),
+    /// Output isn't a terminal, which the viewer draws on
+    NotTerminal,
     /// Image error
     Image(image::error::ImageError),
+    /// Image too large for the decoding limits
+    Limits(image::error::LimitError),
     /// Libsixel error
     Sixel(sixel_rs::status::Error),
     /// ImageSize error
@@ -14,18 +60,95 @@ pub enum Error {
     Channel(crossbeam_channel::SendError<bool>),
     /// CTRL-C error
     Ctrlc(ctrlc::Error),
+    /// Config file error
+    Config(toml::de::Error),
+    /// Glob pattern error
+    Glob(glob::PatternError),
+    /// SVG error
+    #[cfg(feature = "svg")]
+    Svg(resvg::usvg::Error),
+}
+
+impl Error {
+    /// Attach the path of the file that failed
+    pub fn with_path(self, path: &Path) -> Self {
+        match self {
+            Error::File(..) | Error::Failed(_) => self,
+            err => Error::File(path.to_path_buf(), Box::new(err)),
+        }
+    }
+
+    /// Path of the file that failed, if any
+    pub fn path(&self) -> Option<&Path> {
+        match self {
+            Error::File(path, _) => Some(path),
+            _ => None,
+        }
+    }
+
+    /// Category of the error
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            Error::File(_, err) => err.kind(),
+            Error::Failed(_) => ErrorKind::Partial,
+            Error::Io(err) => io_kind(err),
+            Error::Tty(_) | Error::NotTerminal => ErrorKind::Terminal,
+            Error::Image(image::ImageError::IoError(err)) => io_kind(err),
+            Error::Image(image::ImageError::Unsupported(_)) => ErrorKind::Unsupported,
+            Error::Image(image::ImageError::Decoding(_)) | Error::Limits(_) => ErrorKind::Decode,
+            Error::ImageSize(imagesize::ImageError::IoError(err)) => io_kind(err),
+            Error::ImageSize(imagesize::ImageError::NotSupported) => ErrorKind::Unsupported,
+            Error::ImageSize(imagesize::ImageError::CorruptedImage) => ErrorKind::Decode,
+            Error::Config(_) | Error::Glob(_) => ErrorKind::Config,
+            #[cfg(feature = "svg")]
+            Error::Svg(_) => ErrorKind::Decode,
+            _ => ErrorKind::Other,
+        }
+    }
+
+    /// Exit code to report this error with
+    pub fn exit_code(&self) -> i32 {
+        self.kind().exit_code()
+    }
+}
+
+/// Truncated or invalid data is a decoding problem, not an I/O one
+fn io_kind(err: &std::io::Error) -> ErrorKind {
+    match err.kind() {
+        std::io::ErrorKind::NotFound => ErrorKind::NotFound,
+        std::io::ErrorKind::UnexpectedEof | std::io::ErrorKind::InvalidData => ErrorKind::Decode,
+        _ => ErrorKind::Other,
+    }
 }
 
 impl std::fmt::Display for Error {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         match self {
+            Error::File(path, err) => write!(f, "{}: {err}", path.display()),
+            Error::Failed(failures) => {
+                write!(f, "{} image(s) couldn't be previewed:", failures.len())?;
+                for err in failures {
+                    write!(f, "\n  {err}")?;
+                }
+                Ok(())
+            }
             Error::Io(err) => write!(f, "IO error: {err}"),
+            Error::Tty(err) => write!(f, "TTY error: unable to open controlling terminal ({err})"),
+            Error::NotTerminal => write!(f, "TTY error: the viewer needs stdout to be a terminal"),
             Error::Image(err) => write!(f, "Image error: {err}"),
+            Error::Limits(err) => write!(
+                f,
+                "Limits error: {err}, see --max-dimension and --max-memory"
+            ),
             Error::Sixel(err) => write!(f, "Sixel error: {err:#?}"),
             Error::ImageSize(err) => write!(f, "Image size error: {err}"),
             Error::Tempfile(err) => write!(f, "Tempfile error: {err}"),
             Error::Channel(err) => write!(f, "Channel error: {err}"),
             Error::Ctrlc(err) => write!(f, "CTRL-C error: {err}"),
+            Error::Config(err) => write!(f, "Config error: {}", err.to_string().trim_end()),
+            Error::Glob(err) => write!(f, "Glob error: {err}"),
+            #[cfg(feature = "svg")]
+            Error::Svg(err) => write!(f, "SVG error: {err}"),
         }
     }
 }
@@ -39,7 +162,10 @@ impl From<std::io::Error> for Error {
 }
 impl From<image::ImageError> for Error {
     fn from(err: image::ImageError) -> Self {
-        Error::Image(err)
+        match err {
+            image::ImageError::Limits(err) => Error::Limits(err),
+            err => Error::Image(err),
+        }
     }
 }
 
@@ -73,4 +199,109 @@ impl From<ctrlc::Error> for Error {
     }
 }
 
+impl From<toml::de::Error> for Error {
+    fn from(err: toml::de::Error) -> Self {
+        Error::Config(err)
+    }
+}
+
+impl From<glob::PatternError> for Error {
+    fn from(err: glob::PatternError) -> Self {
+        Error::Glob(err)
+    }
+}
+
+#[cfg(feature = "svg")]
+impl From<resvg::usvg::Error> for Error {
+    fn from(err: resvg::usvg::Error) -> Self {
+        Error::Svg(err)
+    }
+}
+
 pub type Result<T = ()> = std::result::Result<T, Error>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::error::{
+        DecodingError, ImageFormatHint, LimitError, LimitErrorKind, UnsupportedError,
+    };
+    use std::io::ErrorKind as IoKind;
+
+    fn io(kind: IoKind) -> std::io::Error {
+        std::io::Error::from(kind)
+    }
+
+    #[test]
+    fn kinds_have_their_documented_exit_codes() {
+        let codes = [
+            (ErrorKind::Other, 1),
+            (ErrorKind::NotFound, 3),
+            (ErrorKind::Unsupported, 4),
+            (ErrorKind::Decode, 5),
+            (ErrorKind::Terminal, 6),
+            (ErrorKind::Config, 7),
+            (ErrorKind::Partial, 8),
+        ];
+        for (kind, code) in codes {
+            assert_eq!(kind.exit_code(), code, "{kind:?}");
+        }
+    }
+
+    #[test]
+    fn errors_are_categorized() {
+        let unsupported =
+            image::ImageError::Unsupported(UnsupportedError::from(ImageFormatHint::Unknown));
+        let decoding =
+            image::ImageError::Decoding(DecodingError::from_format_hint(ImageFormatHint::Unknown));
+        let limits =
+            image::ImageError::Limits(LimitError::from_kind(LimitErrorKind::DimensionError));
+        let errors = [
+            (Error::Io(io(IoKind::NotFound)), ErrorKind::NotFound),
+            (Error::Io(io(IoKind::UnexpectedEof)), ErrorKind::Decode),
+            (Error::Io(io(IoKind::InvalidData)), ErrorKind::Decode),
+            (Error::Io(io(IoKind::PermissionDenied)), ErrorKind::Other),
+            (Error::Tty(io(IoKind::NotFound)), ErrorKind::Terminal),
+            (Error::NotTerminal, ErrorKind::Terminal),
+            (Error::from(unsupported), ErrorKind::Unsupported),
+            (Error::from(decoding), ErrorKind::Decode),
+            (Error::from(limits), ErrorKind::Decode),
+            (
+                Error::from(image::ImageError::IoError(io(IoKind::NotFound))),
+                ErrorKind::NotFound,
+            ),
+            (
+                Error::ImageSize(imagesize::ImageError::NotSupported),
+                ErrorKind::Unsupported,
+            ),
+            (
+                Error::ImageSize(imagesize::ImageError::CorruptedImage),
+                ErrorKind::Decode,
+            ),
+            (
+                Error::Config(toml::from_str::<toml::Table>("=").unwrap_err()),
+                ErrorKind::Config,
+            ),
+            (
+                Error::Glob(glob::Pattern::new("[").unwrap_err()),
+                ErrorKind::Config,
+            ),
+            (Error::Failed(Vec::new()), ErrorKind::Partial),
+        ];
+        for (err, kind) in errors {
+            assert_eq!(err.kind(), kind, "{err}");
+        }
+    }
+
+    #[test]
+    fn file_errors_keep_the_kind_of_their_cause() {
+        let err = Error::Io(io(IoKind::NotFound)).with_path(Path::new("missing.png"));
+        assert_eq!(err.path(), Some(Path::new("missing.png")));
+        assert_eq!(err.kind(), ErrorKind::NotFound);
+        assert_eq!(err.exit_code(), 3);
+
+        // Failures are reported as partial even when they all have the same cause
+        let err = Error::Failed(vec![err]);
+        assert_eq!(err.exit_code(), 8);
+    }
+}
@@ -1,7 +1,14 @@
-use crate::{options::Options, result::Result};
+use crate::{
+    options::Options,
+    result::Result,
+    utils::{open_tty, poll_tty, RawMode},
+};
 use clap::ValueEnum;
-use console::{Key, Term};
-use std::{env, io::Write};
+use serde::Deserialize;
+use std::{
+    env,
+    io::{IsTerminal, Read, Write},
+};
 
 // add supported Terminals based on their eventual environment variables
 const KITTY_SUPPORTED: [&str; 2] = ["xterm-kitty", "WezTerm"];
@@ -18,8 +25,12 @@ const SIXEL_SUPPORTED: [&str; 7] = [
 
 const ITERM_SUPPORTED: [&str; 3] = ["iTerm", "WezTerm", "mintty"];
 
+/// How long terminals are waited for when queried, in milliseconds
+const QUERY_TIMEOUT: i32 = 500;
+
 /// Supported previewing protocols
-#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub enum Protocol {
     Kitty,
     Sixel,
@@ -74,6 +85,87 @@ impl Protocol {
     }
 }
 
+/// Supported colour depths for Unicode blocks
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ColorMode {
+    Truecolor,
+    Ansi256,
+}
+
+impl ColorMode {
+    /// Choose the best colour depth for previewing
+    pub fn choose(options: &Options) -> Self {
+        if let Some(colors) = options.colors {
+            colors
+        } else if truecolor() {
+            ColorMode::Truecolor
+        } else {
+            ColorMode::Ansi256
+        }
+    }
+}
+
+/// Background to composite transparent pixels over
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Deserialize)]
+#[serde(try_from = "String")]
+pub enum Background {
+    /// Query the terminal background colour
+    Auto,
+    /// Let the terminal handle transparency
+    None,
+    /// Light and dark grey squares
+    Checkerboard,
+    /// Given rgb colour
+    Color([u8; 3]),
+}
+
+impl Background {
+    /// Choose the background to composite over, `Auto` is resolved by querying the terminal
+    ///
+    /// Output going elsewhere or laid out for a virtual terminal isn't shown on the
+    /// terminal attached, whose background doesn't apply
+    pub fn choose(options: &Options) -> Self {
+        let is_virtual = options.term_cols.is_some() || options.term_rows.is_some();
+        match options.background.unwrap_or(Background::Auto) {
+            Background::Auto if is_virtual || !std::io::stdout().is_terminal() => Background::None,
+            Background::Auto => background_color().map_or(Background::None, Background::Color),
+            background => background,
+        }
+    }
+}
+
+impl std::str::FromStr for Background {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "auto" => Ok(Background::Auto),
+            "none" => Ok(Background::None),
+            "checkerboard" => Ok(Background::Checkerboard),
+            _ => {
+                let hex = s.trim_start_matches('#');
+                let value = u32::from_str_radix(hex, 16)
+                    .ok()
+                    .filter(|_| hex.len() == 6 && hex.chars().all(|c| c.is_ascii_hexdigit()))
+                    .ok_or(format!(
+                        "invalid background '{s}', expected auto, none, checkerboard or #rrggbb"
+                    ))?;
+                let [_, r, g, b] = value.to_be_bytes();
+                Ok(Background::Color([r, g, b]))
+            }
+        }
+    }
+}
+
+impl TryFrom<String> for Background {
+    type Error = String;
+
+    fn try_from(s: String) -> std::result::Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
 impl std::fmt::Display for Protocol {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         match self {
@@ -89,29 +181,103 @@ fn find_match(list: &[&str], var: &str) -> bool {
     list.iter().any(|s| var.contains(s))
 }
 
-fn check_primary_attributes(attrs: &[Vec<&str>], subcommand: Option<&[u8]>) -> Result<bool> {
-    let mut stdout = Term::stdout();
-    let command = [subcommand.unwrap_or_default(), b"\x1b[c"].concat();
-    stdout.write_all(&command)?;
-    stdout.flush()?;
-
-    let mut response = String::new();
-    // what if the terminal doesn't answer ?
-    while !response.contains('c') {
-        match stdout.read_key() {
-            Ok(Key::Char(chr)) => response.push(chr),
-            Ok(Key::UnknownEscSeq(esc)) => response.extend(esc),
-            Err(_) => break,
-            _ => (),
+/// Send a request to the terminal followed by a primary attributes request,
+/// which every terminal answers, and collect the response until that answer
+///
+/// Terminals that stop answering for `QUERY_TIMEOUT` are given up on, with what they sent
+pub(crate) fn query_terminal(request: &[u8]) -> Result<String> {
+    // Talk to the controlling TTY so that redirected stdin/stdout don't matter
+    let mut tty = open_tty()?;
+    let _mode = RawMode::new(&tty, true)?;
+    tty.write_all(&[request, b"\x1b[c"].concat())?;
+    tty.flush()?;
+
+    let mut response = Vec::new();
+    let mut buffer = [0; 256];
+    // primary attributes answer looks like "\x1b[?...c"
+    while !(response.windows(2).any(|bytes| bytes == b"[?") && response.ends_with(b"c")) {
+        if !poll_tty(&tty, QUERY_TIMEOUT) {
+            break;
+        }
+        match tty.read(&mut buffer) {
+            Ok(0) | Err(_) => break,
+            Ok(read) => response.extend_from_slice(&buffer[..read]),
         }
     }
 
+    Ok(String::from_utf8_lossy(&response).into_owned())
+}
+
+fn check_primary_attributes(attrs: &[Vec<&str>], subcommand: Option<&[u8]>) -> Result<bool> {
+    let response = query_terminal(subcommand.unwrap_or_default())?;
+
     // check if each groups of attrs has at least a match
     Ok(attrs.iter().all(|group| find_match(group, &response)))
 }
 
+/// Query the terminal background colour with OSC 11
+pub fn background_color() -> Option<[u8; 3]> {
+    parse_background_color(&query_terminal(b"\x1b]11;?\x1b\\").ok()?)
+}
+
+/// Colour of an OSC 11 answer, which looks like "]11;rgb:rrrr/gggg/bbbb"
+fn parse_background_color(response: &str) -> Option<[u8; 3]> {
+    let start = response.find("rgb:")? + 4;
+    let mut channels = response[start..]
+        .split(|c: char| !c.is_ascii_hexdigit())
+        .take(3)
+        .map(|hex| {
+            // channels can have 1 to 4 hex digits, keep the 8 most significant bits
+            let value = u32::from_str_radix(hex.get(..hex.len().min(4))?, 16).ok()?;
+            let max = (1 << (4 * hex.len().min(4))) - 1;
+            Some((value * 255 / max) as u8)
+        });
+
+    Some([channels.next()??, channels.next()??, channels.next()??])
+}
+
 /// Check if the terminal supports truecolor
 pub fn truecolor() -> bool {
     let colorterm = env::var("COLORTERM").unwrap_or_default();
     matches!(colorterm.as_str(), "truecolor" | "24bit")
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn background_colors_are_parsed() {
+        let color = |s: &str| match s.parse() {
+            Ok(Background::Color(color)) => Some(color),
+            _ => None,
+        };
+        assert_eq!(color("#ff8000"), Some([255, 128, 0]));
+        assert_eq!(color("0a0b0c"), Some([10, 11, 12]));
+        assert!(matches!("auto".parse(), Ok(Background::Auto)));
+        assert!(matches!("none".parse(), Ok(Background::None)));
+        assert!(matches!(
+            "checkerboard".parse(),
+            Ok(Background::Checkerboard)
+        ));
+        assert!("+abcde".parse::<Background>().is_err());
+        assert!("#-abcde".parse::<Background>().is_err());
+        assert!("#fff".parse::<Background>().is_err());
+        assert!("#gggggg".parse::<Background>().is_err());
+    }
+
+    #[test]
+    fn terminal_backgrounds_are_parsed() {
+        let answer = |rgb: &str| parse_background_color(&format!("\x1b]11;rgb:{rgb}\x1b\\"));
+        assert_eq!(answer("f/8/0"), Some([255, 136, 0]));
+        assert_eq!(answer("ff/80/00"), Some([255, 128, 0]));
+        assert_eq!(answer("ffff/8000/0000"), Some([255, 127, 0]));
+        // Answers can end with BEL too, and come after the primary attributes
+        assert_eq!(
+            parse_background_color("\x1b]11;rgb:1a1a/1a1a/1a1a\x07\x1b[?62;4c"),
+            Some([26, 26, 26])
+        );
+        assert_eq!(parse_background_color("\x1b[?62;4c"), None);
+        assert_eq!(answer("ffff/8000"), None);
+    }
+}
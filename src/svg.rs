@@ -0,0 +1,129 @@
+use crate::decode::check_limits;
+use crate::layout;
+use crate::options::Options;
+use crate::result::{Error, Result};
+use crate::transform::{kept_region, rotate_and_flip, transformed_size, Region};
+use crate::utils::TermSize;
+use image::error::{LimitError, LimitErrorKind};
+use image::{DynamicImage, RgbaImage};
+use resvg::{tiny_skia, usvg};
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+/// Check if a buffer looks like an SVG document
+pub fn is_svg(buffer: &[u8]) -> bool {
+    let start = String::from_utf8_lossy(&buffer[..buffer.len().min(1024)]);
+    let start = start.trim_start_matches('\u{feff}').trim_start();
+
+    (start.starts_with("<svg") || start.starts_with("<?xml") || start.starts_with("<!"))
+        && start.contains("<svg")
+}
+
+/// Check if a file looks like an SVG document, only reading its beginning
+pub fn is_svg_file(image_path: &Path) -> Result<bool> {
+    let mut buffer = Vec::with_capacity(1024);
+    File::open(image_path)?
+        .take(1024)
+        .read_to_end(&mut buffer)?;
+    Ok(is_svg(&buffer))
+}
+
+/// A parsed SVG document, ready to be rasterized at any size
+pub struct Svg {
+    tree: usvg::Tree,
+}
+
+impl Svg {
+    /// Parse an SVG document
+    pub fn parse(buffer: &[u8]) -> Result<Self> {
+        let mut options = usvg::Options::default();
+        // Loading fonts is slow, only do it if they're needed
+        if buffer.windows(5).any(|window| window == b"<text") {
+            options.fontdb_mut().load_system_fonts();
+        }
+
+        Ok(Self {
+            tree: usvg::Tree::from_data(buffer, &options)?,
+        })
+    }
+
+    /// Open and parse an SVG file
+    pub fn open(image_path: &Path) -> Result<Self> {
+        Svg::parse(&std::fs::read(image_path)?)
+    }
+
+    /// Intrinsic size of the document
+    pub fn size(&self) -> (u32, u32) {
+        let size = self.tree.size().to_int_size();
+        (size.width(), size.height())
+    }
+
+    /// Rasterize the document at exactly the given size
+    pub fn render(&self, width: u32, height: u32) -> Result<DynamicImage> {
+        let (width, height) = (width.max(1), height.max(1));
+        let region = Region {
+            x: 0,
+            y: 0,
+            width,
+            height,
+        };
+        self.render_region((width, height), region)
+    }
+
+    /// Rasterize a region of the document scaled to the given size, without
+    /// drawing the rest of it
+    fn render_region(&self, (width, height): (u32, u32), region: Region) -> Result<DynamicImage> {
+        let mut pixmap = tiny_skia::Pixmap::new(region.width.max(1), region.height.max(1))
+            .ok_or_else(|| LimitError::from_kind(LimitErrorKind::DimensionError))
+            .map_err(Error::Limits)?;
+
+        let size = self.tree.size();
+        let transform = tiny_skia::Transform::from_row(
+            width as f32 / size.width(),
+            0.0,
+            0.0,
+            height as f32 / size.height(),
+            -(region.x as f32),
+            -(region.y as f32),
+        );
+        resvg::render(&self.tree, transform, &mut pixmap.as_mut());
+
+        // tiny-skia works with premultiplied alpha
+        let (width, height) = (pixmap.width(), pixmap.height());
+        let pixels = pixmap
+            .pixels()
+            .iter()
+            .flat_map(|pixel| {
+                let color = pixel.demultiply();
+                [color.red(), color.green(), color.blue(), color.alpha()]
+            })
+            .collect();
+        let image = RgbaImage::from_raw(width, height, pixels).expect("buffer matches pixmap size");
+
+        Ok(DynamicImage::ImageRgba8(image))
+    }
+
+    /// Rasterize the part of the document shown once transformed, so that once
+    /// fitted in the terminal it has as many pixels as the cells it takes
+    ///
+    /// Only the kept region is drawn, zooming in doesn't rasterize the whole
+    /// document at the zoomed scale
+    pub fn render_fitted(&self, term_size: &TermSize, options: &Options) -> Result<DynamicImage> {
+        let (width, height) = self.size();
+        let (shown_width, shown_height) = transformed_size((width, height), options);
+        let fit = layout::fit(term_size, shown_width, shown_height, options)?;
+        let (col_size, row_size) = term_size.cell_size();
+
+        let scale = fit.scale(fit.cols * col_size, fit.rows * row_size);
+        let scaled = (
+            (f64::from(width) * scale).round().max(1.0) as u32,
+            (f64::from(height) * scale).round().max(1.0) as u32,
+        );
+        let region = kept_region(scaled, (width, height), options);
+        check_limits((region.width, region.height), options)?;
+
+        let image = self.render_region(scaled, region)?;
+        Ok(rotate_and_flip(image, options))
+    }
+}
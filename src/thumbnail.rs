@@ -0,0 +1,230 @@
+use crate::cache::cache_home;
+use crate::result::Result;
+use clap::ValueEnum;
+use image::{DynamicImage, ImageFormat};
+use serde::Deserialize;
+use std::fs::{self, DirBuilder, File};
+use std::io::BufWriter;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::DirBuilderExt;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+/// Sizes of the freedesktop thumbnails, they fit in squares of these sizes
+const FLAVORS: [(&str, u32); 4] = [
+    ("normal", 128),
+    ("large", 256),
+    ("x-large", 512),
+    ("xx-large", 1024),
+];
+
+/// How the thumbnails made by the desktop are used
+/// (write also creates the missing ones)
+#[derive(Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ThumbnailMode {
+    Read,
+    Write,
+}
+
+/// Directory of the thumbnails, `$XDG_CACHE_HOME/thumbnails` or `~/.cache/thumbnails`
+pub fn dir() -> Option<PathBuf> {
+    Some(cache_home()?.join("thumbnails"))
+}
+
+/// An image file as the thumbnail cache identifies it
+struct Source {
+    uri: String,
+    mtime: u64,
+    len: u64,
+}
+
+impl Source {
+    /// `None` for files that can't have thumbnails (thumbnails themselves)
+    fn new(path: &Path) -> Option<Self> {
+        let path = fs::canonicalize(path).ok()?;
+        if path.starts_with(dir()?) {
+            return None;
+        }
+        let metadata = fs::metadata(&path).ok()?;
+        let mtime = metadata.modified().ok()?.duration_since(UNIX_EPOCH).ok()?;
+
+        Some(Self {
+            uri: uri(&path),
+            mtime: mtime.as_secs(),
+            len: metadata.len(),
+        })
+    }
+
+    /// Thumbnails are named after the MD5 of the file URI
+    fn thumbnail_path(&self, flavor: &str) -> Option<PathBuf> {
+        Some(dir()?.join(flavor).join(name(&self.uri)))
+    }
+
+    /// Check that a thumbnail was made from the current version of the file
+    fn is_valid(&self, info: &png::Info) -> bool {
+        let text = |keyword: &str| {
+            info.uncompressed_latin1_text
+                .iter()
+                .find(|chunk| chunk.keyword == keyword)
+                .map(|chunk| chunk.text.as_str())
+        };
+
+        text("Thumb::URI") == Some(self.uri.as_str())
+            && text("Thumb::MTime").and_then(|mtime| mtime.parse().ok()) == Some(self.mtime)
+            // The size is optional, but has to match when given
+            && text("Thumb::Size").is_none_or(|len| len.parse().ok() == Some(self.len))
+    }
+}
+
+/// `file://` URI of an absolute path, percent-encoded the way GLib does
+fn uri(path: &Path) -> String {
+    let mut uri = String::from("file://");
+    for &byte in path.as_os_str().as_bytes() {
+        if byte.is_ascii_alphanumeric() || b"!$&'()*+,-./:=@_~".contains(&byte) {
+            uri.push(char::from(byte));
+        } else {
+            uri.push_str(&format!("%{byte:02X}"));
+        }
+    }
+    uri
+}
+
+/// File name of the thumbnails of a file URI
+fn name(uri: &str) -> String {
+    format!("{:x}.png", md5::compute(uri))
+}
+
+/// Size of the thumbnail to make for a preview of the given size, from an image of the
+/// given size, `None` if the preview is too large for thumbnails
+pub fn thumbnail_size((width, height): (u32, u32), scaled: (u32, u32)) -> Option<(u32, u32)> {
+    let (_, max_size) = FLAVORS
+        .into_iter()
+        .find(|(_, max_size)| scaled.0.max(scaled.1) <= *max_size)?;
+
+    // Images smaller than the flavor are kept as they are
+    let scale = (f64::from(max_size) / f64::from(width.max(height))).min(1.0);
+    Some((
+        ((f64::from(width) * scale).round() as u32).max(1),
+        ((f64::from(height) * scale).round() as u32).max(1),
+    ))
+}
+
+/// Thumbnail of an image of the given size at least as big as the scaled size, if the
+/// desktop made a valid one
+pub fn load(path: &Path, size: (u32, u32), scaled: (u32, u32)) -> Option<DynamicImage> {
+    let source = Source::new(path)?;
+
+    FLAVORS
+        .into_iter()
+        .filter(|(_, max_size)| scaled.0.max(scaled.1) <= *max_size)
+        .find_map(|(flavor, _)| {
+            let buffer = fs::read(source.thumbnail_path(flavor)?).ok()?;
+            let reader = png::Decoder::new(buffer.as_slice()).read_info().ok()?;
+            let info = reader.info();
+            // Thumbnails made before the image was oriented or edited don't match its ratio
+            let (width, height) = (u64::from(size.0), u64::from(size.1));
+            let matches_ratio = (u64::from(info.width) * height)
+                .abs_diff(u64::from(info.height) * width)
+                <= width.max(height);
+            if !source.is_valid(info)
+                || !matches_ratio
+                || info.width < scaled.0
+                || info.height < scaled.1
+            {
+                return None;
+            }
+
+            image::load_from_memory_with_format(&buffer, ImageFormat::Png).ok()
+        })
+}
+
+/// Save the thumbnail of an image for the desktop and later previews, in the flavor of its size
+pub fn save(path: &Path, thumbnail: &DynamicImage) -> Result {
+    let Some(source) = Source::new(path) else {
+        return Ok(());
+    };
+    let max_size = thumbnail.width().max(thumbnail.height());
+    let Some((flavor, _)) = FLAVORS
+        .into_iter()
+        .find(|(_, flavor_size)| max_size <= *flavor_size)
+    else {
+        return Ok(());
+    };
+    let Some(thumbnail_path) = source.thumbnail_path(flavor) else {
+        return Ok(());
+    };
+    let dir = thumbnail_path.parent().unwrap_or(Path::new("."));
+
+    // Thumbnails are private to the user and written whole or not at all
+    DirBuilder::new().recursive(true).mode(0o700).create(dir)?;
+    let file = tempfile::Builder::new()
+        .prefix("pic-")
+        .suffix(".png")
+        .tempfile_in(dir)?;
+
+    encode(file.as_file(), thumbnail, &source)?;
+    file.persist(thumbnail_path)?;
+    Ok(())
+}
+
+/// Encode a thumbnail with the metadata identifying the image it was made from
+fn encode(file: &File, thumbnail: &DynamicImage, source: &Source) -> std::io::Result<()> {
+    let mut encoder =
+        png::Encoder::new(BufWriter::new(file), thumbnail.width(), thumbnail.height());
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder.add_text_chunk("Thumb::URI".to_string(), source.uri.clone())?;
+    encoder.add_text_chunk("Thumb::MTime".to_string(), source.mtime.to_string())?;
+    encoder.add_text_chunk("Thumb::Size".to_string(), source.len.to_string())?;
+    encoder.add_text_chunk("Software".to_string(), "pic".to_string())?;
+
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(thumbnail.to_rgba8().as_raw())?;
+    writer.finish()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn uris_are_escaped_like_glib() {
+        let uri = |path: &str| uri(Path::new(path));
+        assert_eq!(
+            uri("/home/jens/photos/me.png"),
+            "file:///home/jens/photos/me.png"
+        );
+        assert_eq!(
+            uri("/tmp/a b/\u{e9}t\u{e9}.png"),
+            "file:///tmp/a%20b/%C3%A9t%C3%A9.png"
+        );
+        assert_eq!(
+            uri("/tmp/semi;colon#hash?q.png"),
+            "file:///tmp/semi%3Bcolon%23hash%3Fq.png"
+        );
+        assert_eq!(
+            uri("/tmp/sym!$&'()*+,=@_~[].png"),
+            "file:///tmp/sym!$&'()*+,=@_~%5B%5D.png"
+        );
+        assert_eq!(uri("/tmp/100%.png"), "file:///tmp/100%25.png");
+    }
+
+    #[test]
+    fn thumbnails_are_named_after_the_uri() {
+        // Example of the thumbnail spec
+        assert_eq!(
+            name("file:///home/jens/photos/me.png"),
+            "c6ee772d9e49320e97ec29a7eb5b1697.png"
+        );
+        assert_eq!(
+            name("file:///tmp/u/a%20b/%C3%A9t%C3%A9.png"),
+            "58907b8342112f04a37d2c93d2cf1215.png"
+        );
+        assert_eq!(
+            name("file:///tmp/u/a%20b/semi%3Bcolon%23hash%3Fq.png"),
+            "7813241e8c9f3f6aadb3450934a5f88a.png"
+        );
+    }
+}
@@ -0,0 +1,315 @@
+use crate::options::Options;
+use clap::ValueEnum;
+use image::DynamicImage;
+
+/// Region of an image, in pixels
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Region {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl std::str::FromStr for Region {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let error = || format!("invalid region '{s}', expected x,y,width,height");
+        let values = s
+            .split(',')
+            .map(|value| value.trim().parse::<u32>())
+            .collect::<std::result::Result<Vec<u32>, _>>()
+            .map_err(|_| error())?;
+
+        match values[..] {
+            [x, y, width, height] if width > 0 && height > 0 => Ok(Region {
+                x,
+                y,
+                width,
+                height,
+            }),
+            _ => Err(error()),
+        }
+    }
+}
+
+/// Zoom factor and the point it's centred on, in percent of the image
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Zoom {
+    pub factor: f64,
+    pub center: (f64, f64),
+}
+
+impl std::str::FromStr for Zoom {
+    type Err = String;
+
+    /// `factor` or `factor@x,y`
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let error = || format!("invalid zoom '{s}', expected FACTOR or FACTOR@X,Y");
+        let (factor, center) = s.split_once('@').unwrap_or((s, "50,50"));
+        let factor: f64 = factor.trim().parse().map_err(|_| error())?;
+        let (x, y) = center.split_once(',').ok_or_else(error)?;
+        let (x, y): (f64, f64) = (
+            x.trim().parse().map_err(|_| error())?,
+            y.trim().parse().map_err(|_| error())?,
+        );
+
+        if factor < 1.0 || !(0.0..=100.0).contains(&x) || !(0.0..=100.0).contains(&y) {
+            return Err(error());
+        }
+        Ok(Zoom {
+            factor,
+            center: (x, y),
+        })
+    }
+}
+
+/// Clockwise rotation of images
+#[derive(Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, ValueEnum)]
+pub enum Rotation {
+    #[value(name = "90")]
+    Quarter,
+    #[value(name = "180")]
+    Half,
+    #[value(name = "270")]
+    ThreeQuarters,
+}
+
+/// Axis images are flipped along
+#[derive(Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, ValueEnum)]
+pub enum Flip {
+    #[value(name = "h", alias = "horizontal")]
+    Horizontal,
+    #[value(name = "v", alias = "vertical")]
+    Vertical,
+}
+
+/// Check if images have to be transformed before being displayed
+pub fn is_transformed(options: &Options) -> bool {
+    options.crop.is_some()
+        || options.zoom.is_some()
+        || options.rotate.is_some()
+        || options.flip.is_some()
+}
+
+/// Part of an image of the given size kept by crop and zoom, regions are given
+/// for an image of the reference size (SVGs are rasterized at another size)
+pub fn kept_region(
+    (width, height): (u32, u32),
+    (reference_width, reference_height): (u32, u32),
+    options: &Options,
+) -> Region {
+    // Headers can report empty images, which still keep one pixel
+    let (width, height) = (width.max(1), height.max(1));
+    let mut region = Region {
+        x: 0,
+        y: 0,
+        width,
+        height,
+    };
+
+    if let Some(crop) = options.crop {
+        let scale_x = |value: u32| {
+            (u64::from(value) * u64::from(width) / u64::from(reference_width.max(1))) as u32
+        };
+        let scale_y = |value: u32| {
+            (u64::from(value) * u64::from(height) / u64::from(reference_height.max(1))) as u32
+        };
+        let x = scale_x(crop.x).min(width.saturating_sub(1));
+        let y = scale_y(crop.y).min(height.saturating_sub(1));
+        region = Region {
+            x,
+            y,
+            width: scale_x(crop.width).clamp(1, width - x),
+            height: scale_y(crop.height).clamp(1, height - y),
+        };
+    }
+
+    if let Some(zoom) = options.zoom {
+        let zoomed_width = ((f64::from(region.width) / zoom.factor) as u32).max(1);
+        let zoomed_height = ((f64::from(region.height) / zoom.factor) as u32).max(1);
+        // Keep the zoomed region inside the image
+        let center_x = f64::from(region.width) * zoom.center.0 / 100.0;
+        let center_y = f64::from(region.height) * zoom.center.1 / 100.0;
+        let x = (center_x - f64::from(zoomed_width) / 2.0)
+            .clamp(0.0, f64::from(region.width - zoomed_width)) as u32;
+        let y = (center_y - f64::from(zoomed_height) / 2.0)
+            .clamp(0.0, f64::from(region.height - zoomed_height)) as u32;
+        region = Region {
+            x: region.x + x,
+            y: region.y + y,
+            width: zoomed_width,
+            height: zoomed_height,
+        };
+    }
+
+    region
+}
+
+/// Size of an image once transformed
+pub fn transformed_size(size: (u32, u32), options: &Options) -> (u32, u32) {
+    let region = kept_region(size, size, options);
+    match options.rotate {
+        Some(Rotation::Quarter | Rotation::ThreeQuarters) => (region.height, region.width),
+        _ => (region.width, region.height),
+    }
+}
+
+/// Crop, zoom, rotate and flip an image, regions are given for an image of the reference size
+pub fn transform(image: DynamicImage, reference: (u32, u32), options: &Options) -> DynamicImage {
+    if !is_transformed(options) {
+        return image;
+    }
+
+    let size = (image.width(), image.height());
+    let region = kept_region(size, reference, options);
+    let image = if (region.width, region.height) == size {
+        image
+    } else {
+        image.crop_imm(region.x, region.y, region.width, region.height)
+    };
+
+    rotate_and_flip(image, options)
+}
+
+/// Only rotate and flip an image, for images already cropped and zoomed
+pub fn rotate_and_flip(image: DynamicImage, options: &Options) -> DynamicImage {
+    let image = match options.rotate {
+        Some(Rotation::Quarter) => image.rotate90(),
+        Some(Rotation::Half) => image.rotate180(),
+        Some(Rotation::ThreeQuarters) => image.rotate270(),
+        None => image,
+    };
+
+    match options.flip {
+        Some(Flip::Horizontal) => image.fliph(),
+        Some(Flip::Vertical) => image.flipv(),
+        None => image,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn region(x: u32, y: u32, width: u32, height: u32) -> Region {
+        Region {
+            x,
+            y,
+            width,
+            height,
+        }
+    }
+
+    fn zoom(factor: f64, center: (f64, f64)) -> Zoom {
+        Zoom { factor, center }
+    }
+
+    fn options(crop: Option<Region>, zoom: Option<Zoom>) -> Options {
+        let mut options = Options::new(Vec::new());
+        options.set_transform(crop, zoom, None, None);
+        options
+    }
+
+    #[test]
+    fn parse_region() {
+        assert_eq!("10,20,30,40".parse(), Ok(region(10, 20, 30, 40)));
+        assert_eq!(" 0, 0 ,1,1".parse(), Ok(region(0, 0, 1, 1)));
+        assert!("0,0,0,10".parse::<Region>().is_err());
+        assert!("0,0,10,0".parse::<Region>().is_err());
+        assert!("1,2,3".parse::<Region>().is_err());
+        assert!("-1,0,1,1".parse::<Region>().is_err());
+        assert!("".parse::<Region>().is_err());
+    }
+
+    #[test]
+    fn parse_zoom() {
+        assert_eq!("2".parse(), Ok(zoom(2.0, (50.0, 50.0))));
+        assert_eq!("4@25,75".parse(), Ok(zoom(4.0, (25.0, 75.0))));
+        assert_eq!("1.5@0,100".parse(), Ok(zoom(1.5, (0.0, 100.0))));
+        assert!("0.5".parse::<Zoom>().is_err());
+        assert!("2@101,0".parse::<Zoom>().is_err());
+        assert!("2@50".parse::<Zoom>().is_err());
+        assert!("x2".parse::<Zoom>().is_err());
+    }
+
+    #[test]
+    fn nothing_kept_out() {
+        let options = options(None, None);
+        assert_eq!(
+            kept_region((100, 50), (100, 50), &options),
+            region(0, 0, 100, 50)
+        );
+    }
+
+    #[test]
+    fn crop_is_scaled_to_the_reference() {
+        let options = options(Some(region(10, 10, 20, 20)), None);
+        assert_eq!(
+            kept_region((200, 200), (100, 100), &options),
+            region(20, 20, 40, 40)
+        );
+    }
+
+    #[test]
+    fn crop_outside_the_image_is_clamped() {
+        let options = options(Some(region(80, 80, 50, 50)), None);
+        assert_eq!(
+            kept_region((100, 100), (100, 100), &options),
+            region(80, 80, 20, 20)
+        );
+
+        // At least a pixel is kept
+        let options = self::options(Some(region(150, 150, 50, 50)), None);
+        assert_eq!(
+            kept_region((100, 100), (100, 100), &options),
+            region(99, 99, 1, 1)
+        );
+    }
+
+    #[test]
+    fn zoom_stays_inside_the_image() {
+        let options = options(None, Some(zoom(2.0, (0.0, 100.0))));
+        assert_eq!(
+            kept_region((100, 100), (100, 100), &options),
+            region(0, 50, 50, 50)
+        );
+    }
+
+    #[test]
+    fn zoom_above_the_image_keeps_a_pixel() {
+        let options = options(None, Some(zoom(1000.0, (50.0, 50.0))));
+        assert_eq!(
+            kept_region((100, 50), (100, 50), &options),
+            region(49, 24, 1, 1)
+        );
+    }
+
+    #[test]
+    fn zoom_applies_to_the_crop() {
+        let options = options(Some(region(50, 0, 50, 50)), Some(zoom(2.0, (50.0, 50.0))));
+        assert_eq!(
+            kept_region((100, 100), (100, 100), &options),
+            region(62, 12, 25, 25)
+        );
+    }
+
+    #[test]
+    fn empty_images_keep_a_pixel() {
+        let options = options(Some(region(10, 10, 20, 20)), Some(zoom(2.0, (50.0, 50.0))));
+        assert_eq!(kept_region((0, 0), (0, 0), &options), region(0, 0, 1, 1));
+        assert_eq!(
+            kept_region((0, 50), (100, 50), &options),
+            region(0, 15, 1, 10)
+        );
+    }
+
+    #[test]
+    fn rotation_swaps_the_size() {
+        let mut options = options(Some(region(0, 0, 30, 10)), None);
+        assert_eq!(transformed_size((100, 100), &options), (30, 10));
+        options.rotate = Some(Rotation::ThreeQuarters);
+        assert_eq!(transformed_size((100, 100), &options), (10, 30));
+    }
+}
@@ -1,12 +1,76 @@
-use crate::{result::Result, support};
+use crate::{
+    options::Options,
+    result::Result,
+    support::{Background, ColorMode},
+};
 use ansi_colours::ansi256_from_rgb;
-use crossbeam_channel::{unbounded, Receiver, Sender};
-use image::{codecs::png::PngEncoder, DynamicImage, ImageEncoder};
+use crossbeam_channel::{select, unbounded, Receiver, Sender};
+use image::{
+    codecs::{
+        gif::{GifEncoder, Repeat},
+        png::PngEncoder,
+    },
+    Delay, DynamicImage, Frame, ImageEncoder, RgbaImage,
+};
 use std::{
-    fs::File,
+    env,
+    fs::{self, File, OpenOptions},
     io::{Error, Write},
+    os::unix::{fs::MetadataExt, io::AsRawFd},
     path::PathBuf,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex, Once, OnceLock,
+    },
+    time::Duration,
 };
+use tempfile::NamedTempFile;
+
+/// Prefix of the temporary files kitty previews are sent through
+pub(crate) const KITTY_PREFIX: &str = "pic.tty-graphics-protocol.";
+/// Prefix of the temporary files sixel previews are encoded through
+pub(crate) const SIXEL_PREFIX: &str = "pic.sixel.";
+/// Temporary files to remove if the process is interrupted
+static TEMP_FILES: Mutex<Vec<PathBuf>> = Mutex::new(Vec::new());
+/// Number of previews waiting for CTRL-C to stop gracefully
+static CTRLC_LISTENERS: AtomicUsize = AtomicUsize::new(0);
+/// Channels to the process-wide CTRL-C handler, which can only be set once
+static CTRLC_CHANNELS: OnceLock<(Sender<bool>, Receiver<bool>)> = OnceLock::new();
+/// Files older than this, left by previews that failed, are swept
+const STALE_TEMP_FILE: Duration = Duration::from_secs(10 * 60);
+
+/// Install the process-wide CTRL-C handler, which removes the temporary files of the
+/// process and lets previews restore the terminal before exiting
+///
+/// Only the `pic` binary installs it, programs previewing images handle signals themselves
+pub fn install_ctrlc_handler() -> Result {
+    if CTRLC_CHANNELS.get().is_some() {
+        return Ok(());
+    }
+
+    // We use two channels so that they can communicate
+    let (ctrlc_tx, preview_rx) = unbounded();
+    let (preview_tx, ctrlc_rx) = unbounded();
+
+    ctrlc::set_handler(move || {
+        remove_temp_files();
+        if CTRLC_LISTENERS.load(Ordering::SeqCst) == 0 {
+            std::process::exit(130);
+        }
+
+        ctrlc_tx
+            .send(true)
+            .expect("CTRL-C error: Unable to send message");
+
+        ctrlc_rx
+            .recv()
+            .expect("CTRL-C error: No response from main thread");
+        std::process::exit(0);
+    })?;
+
+    CTRLC_CHANNELS.get_or_init(|| (preview_tx, preview_rx));
+    Ok(())
+}
 
 pub(crate) struct CtrlcHandler {
     pub sender: Sender<bool>,
@@ -14,29 +78,27 @@ pub(crate) struct CtrlcHandler {
 }
 
 impl CtrlcHandler {
+    /// Without the CTRL-C handler installed, previews are never told to stop
     pub fn new() -> Result<Self> {
-        // We use two channels so that they can communicate
-        let (ctrlc_tx, preview_rx) = unbounded();
-        let (preview_tx, ctrlc_rx) = unbounded();
-
-        ctrlc::set_handler(move || {
-            ctrlc_tx
-                .send(true)
-                .expect("CTRL-C error: Unable to send message");
-
-            ctrlc_rx
-                .recv()
-                .expect("CTRL-C error: No response from main thread");
-            std::process::exit(0);
-        })?;
-
-        Ok(Self {
-            sender: preview_tx,
-            receiver: preview_rx,
-        })
+        let (sender, receiver) = match CTRLC_CHANNELS.get() {
+            Some(channels) => channels.clone(),
+            None => unbounded(),
+        };
+        CTRLC_LISTENERS.fetch_add(1, Ordering::SeqCst);
+
+        Ok(Self { sender, receiver })
+    }
+}
+
+impl Drop for CtrlcHandler {
+    fn drop(&mut self) {
+        CTRLC_LISTENERS.fetch_sub(1, Ordering::SeqCst);
     }
 }
 
+/// Size of a terminal cell in pixels when the terminal doesn't report it
+const DEFAULT_CELL_SIZE: (u32, u32) = (15, 30);
+
 /// Useful handle for terminal size
 #[derive(Clone, Default, Debug)]
 pub struct TermSize {
@@ -68,8 +130,38 @@ impl TermSize {
         Some((self.width / self.cols, self.height / self.rows))
     }
 
-    /// Create TermSize by getting the terminal size with an IOCTL
+    /// Size of a terminal cell in pixels, a common one if the terminal doesn't tell
+    pub fn cell_size(&self) -> (u32, u32) {
+        match self.get_cell_size() {
+            Some((width, height)) if width > 0 && height > 0 => (width, height),
+            _ => DEFAULT_CELL_SIZE,
+        }
+    }
+
+    /// Create TermSize from the virtual geometry given in options,
+    /// falling back on the controlling TTY for anything missing
+    pub fn from_options(options: &Options) -> Result<Self> {
+        let mut term_size = match (options.term_cols, options.term_rows) {
+            (Some(cols), Some(rows)) => TermSize {
+                cols,
+                rows,
+                ..TermSize::default()
+            },
+            _ => TermSize::from_ioctl()?,
+        };
+
+        let (col_size, row_size) = term_size.cell_size();
+        let col_size = options.cell_width.unwrap_or(col_size);
+        let row_size = options.cell_height.unwrap_or(row_size);
+        term_size.width = term_size.cols * col_size;
+        term_size.height = term_size.rows * row_size;
+
+        Ok(term_size)
+    }
+
+    /// Create TermSize by getting the terminal size with an IOCTL on the controlling TTY
     pub fn from_ioctl() -> Result<Self> {
+        let tty = open_tty()?;
         // TODO: find a way to make that safe
         unsafe {
             let mut ws = libc::winsize {
@@ -78,7 +170,7 @@ impl TermSize {
                 ws_xpixel: 0,
                 ws_ypixel: 0,
             };
-            let ret = libc::ioctl(0, libc::TIOCGWINSZ, &mut ws);
+            let ret = libc::ioctl(tty.as_raw_fd(), libc::TIOCGWINSZ, &mut ws);
             if ret == 0 {
                 Ok(TermSize::new(
                     ws.ws_row,
@@ -93,14 +185,135 @@ impl TermSize {
     }
 }
 
-/// Create a temporary file with the given prefix
-pub fn create_temp_file(prefix: &str) -> Result<(File, PathBuf)> {
-    let (tempfile, pathbuf) = tempfile::Builder::new()
+/// Open the controlling TTY, even when stdin/stdout are redirected
+pub fn open_tty() -> Result<File> {
+    OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open("/dev/tty")
+        .map_err(crate::result::Error::Tty)
+}
+
+/// Mode of a TTY with keys read as soon as they're pressed and not echoed,
+/// the previous mode is restored when it is dropped
+pub(crate) struct RawMode {
+    tty: File,
+    termios: libc::termios,
+}
+
+impl RawMode {
+    /// CTRL-C is read as a key unless signals are kept
+    pub fn new(tty: &File, signals: bool) -> Result<Self> {
+        let tty = tty.try_clone()?;
+        let fd = tty.as_raw_fd();
+
+        // SAFETY: termios is initialized by tcgetattr before being read
+        let termios = unsafe {
+            let mut termios = std::mem::zeroed::<libc::termios>();
+            if libc::tcgetattr(fd, &mut termios) != 0 {
+                return Err(Error::last_os_error().into());
+            }
+            termios
+        };
+        // Output processing is kept, previews rely on newlines going back to the first column
+        let mut raw = termios;
+        raw.c_lflag &= !(libc::ICANON | libc::ECHO);
+        if !signals {
+            raw.c_lflag &= !libc::ISIG;
+        }
+        raw.c_cc[libc::VMIN] = 1;
+        raw.c_cc[libc::VTIME] = 0;
+        // SAFETY: raw is a valid termios
+        if unsafe { libc::tcsetattr(fd, libc::TCSADRAIN, &raw) } != 0 {
+            return Err(Error::last_os_error().into());
+        }
+
+        Ok(Self { tty, termios })
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        // SAFETY: termios was filled by tcgetattr
+        unsafe { libc::tcsetattr(self.tty.as_raw_fd(), libc::TCSADRAIN, &self.termios) };
+    }
+}
+
+/// Wait for input on a TTY, false if none came before the timeout (in milliseconds)
+pub(crate) fn poll_tty(tty: &File, timeout: i32) -> bool {
+    let mut pollfd = libc::pollfd {
+        fd: tty.as_raw_fd(),
+        events: libc::POLLIN,
+        revents: 0,
+    };
+    // SAFETY: pollfd is a valid pollfd for the duration of the call
+    unsafe { libc::poll(&mut pollfd, 1, timeout) > 0 }
+}
+
+/// Create a temporary file with the given prefix and suffix in `TMPDIR`, only readable
+/// by the user (tempfile creates them with mode 0600)
+///
+/// It is removed when dropped or if the process is interrupted, unless kept with
+/// [`keep_temp_file`]
+pub fn create_temp_file(prefix: &str, suffix: &str) -> Result<NamedTempFile> {
+    static SWEEP: Once = Once::new();
+    SWEEP.call_once(|| {
+        sweep_temp_files(KITTY_PREFIX);
+        sweep_temp_files(SIXEL_PREFIX);
+    });
+
+    let tempfile = tempfile::Builder::new()
         .prefix(prefix)
-        .tempfile_in("/tmp/")?
-        .keep()?;
+        .suffix(suffix)
+        .tempfile()?;
+    if let Ok(mut temp_files) = TEMP_FILES.lock() {
+        temp_files.push(tempfile.path().to_path_buf());
+    }
+    Ok(tempfile)
+}
 
-    Ok((tempfile, pathbuf))
+/// Keep a temporary file handed to the terminal, which removes it once read
+pub fn keep_temp_file(tempfile: NamedTempFile) -> Result {
+    let (_, path) = tempfile.keep()?;
+    if let Ok(mut temp_files) = TEMP_FILES.lock() {
+        temp_files.retain(|temp_file| *temp_file != path);
+    }
+    Ok(())
+}
+
+/// Remove the temporary files of the process that are still there
+fn remove_temp_files() {
+    if let Ok(temp_files) = TEMP_FILES.lock() {
+        for path in temp_files.iter() {
+            let _ = fs::remove_file(path);
+        }
+    }
+}
+
+/// Remove the stale temporary files with the given prefix that belong to the user,
+/// left by previews that were killed or sent to terminals that don't remove them
+fn sweep_temp_files(prefix: &str) {
+    let Ok(entries) = fs::read_dir(env::temp_dir()) else {
+        return;
+    };
+    // SAFETY: getuid can't fail
+    let uid = unsafe { libc::getuid() };
+
+    for entry in entries.flatten() {
+        let stale = entry.file_name().to_string_lossy().starts_with(prefix)
+            && entry.metadata().is_ok_and(|metadata| {
+                metadata.is_file()
+                    && metadata.uid() == uid
+                    && metadata
+                        .modified()
+                        .ok()
+                        .and_then(|modified| modified.elapsed().ok())
+                        .is_some_and(|age| age > STALE_TEMP_FILE)
+            });
+        if stale {
+            let _ = fs::remove_file(entry.path());
+        }
+    }
 }
 
 /// Save buffer in a temporary file
@@ -111,7 +324,6 @@ pub fn save_in_temp_file(buffer: &[u8], file: &mut File) -> Result {
 }
 
 /// Save terminal cursor position
-#[allow(dead_code)]
 pub fn save_cursor(stdout: &mut impl Write) -> Result {
     stdout.write_all(b"\x1b[s")?;
     stdout.flush()?;
@@ -119,7 +331,6 @@ pub fn save_cursor(stdout: &mut impl Write) -> Result {
 }
 
 /// Restore terminal cursor position
-#[allow(dead_code)]
 pub fn restore_cursor(stdout: &mut impl Write) -> Result {
     stdout.write_all(b"\x1b[u")?;
     stdout.flush()?;
@@ -178,6 +389,51 @@ pub fn move_cursor(stdout: &mut impl Write, col: Option<u32>, row: Option<u32>)
     }
 }
 
+/// Play an animation, displaying each frame after its delay,
+/// until its end (forever if looping) or CTRL-C
+pub fn play_animation<W: Write, T>(
+    stdout: &mut W,
+    frames: &[(Duration, T)],
+    looping: bool,
+    mut display: impl FnMut(&mut W, &T, bool) -> Result,
+) -> Result {
+    // Prevents cursor flickering
+    let handler = CtrlcHandler::new()?;
+    hide_cursor(stdout)?;
+
+    let mut first_frame = true;
+    let mut interrupted = false;
+
+    'animation: loop {
+        for (delay, frame) in frames {
+            select! {
+                default(*delay) => {
+                    display(stdout, frame, first_frame)?;
+                    first_frame = false;
+                },
+                recv(handler.receiver) -> _ => {
+                    interrupted = true;
+                    break 'animation;
+                }
+            }
+        }
+
+        if !looping {
+            break 'animation;
+        }
+    }
+
+    show_cursor(stdout)?;
+    // The CTRL-C handler waits for the cursor to be restored, then exits
+    if interrupted {
+        handler.sender.send(true)?;
+        loop {
+            std::thread::park();
+        }
+    }
+    Ok(())
+}
+
 /// Show terminal cursor
 pub fn show_cursor(stdout: &mut impl Write) -> Result {
     stdout.write_all(b"\x1b[?25h")?;
@@ -192,6 +448,20 @@ pub fn hide_cursor(stdout: &mut impl Write) -> Result {
     Ok(())
 }
 
+/// Switch to the alternate screen, leaving the shell output untouched
+pub fn enter_alternate_screen(stdout: &mut impl Write) -> Result {
+    stdout.write_all(b"\x1b[?1049h")?;
+    stdout.flush()?;
+    Ok(())
+}
+
+/// Switch back from the alternate screen
+pub fn leave_alternate_screen(stdout: &mut impl Write) -> Result {
+    stdout.write_all(b"\x1b[?1049l")?;
+    stdout.flush()?;
+    Ok(())
+}
+
 /// Handle spacing between images
 pub fn handle_spacing(stdout: &mut impl Write, spacing: Option<u32>) -> Result {
     if let Some(spacing) = spacing {
@@ -203,25 +473,25 @@ pub fn handle_spacing(stdout: &mut impl Write, spacing: Option<u32>) -> Result {
 
 /// Fit an images into cols and rows bounds
 pub fn fit_in_bounds(
+    term_size: &TermSize,
     width: u32,
     height: u32,
     cols: Option<u32>,
     rows: Option<u32>,
     upscale: bool,
 ) -> Result<(u32, u32)> {
-    let term_size = TermSize::from_ioctl()?;
-    let (col_size, row_size) = match term_size.get_cell_size() {
-        Some((0, 0)) | None => (15, 30),
-        Some((c, r)) => (c, r),
-    };
-    let cols = cols.unwrap_or(term_size.cols);
+    let (col_size, row_size) = term_size.cell_size();
+    let cols = cols.unwrap_or(term_size.cols).max(1);
     // Terminal prompt puts the image out of screen (rows - 1)
-    let rows = rows.unwrap_or(term_size.rows - 1);
+    let rows = rows.unwrap_or(term_size.rows.saturating_sub(1)).max(1);
 
     let (bound_width, bound_height) = (cols * col_size, rows * row_size);
 
     if !upscale && width < bound_width && height < bound_height {
-        return Ok((width / col_size, height / row_size));
+        return Ok((
+            std::cmp::max(width / col_size, 1),
+            std::cmp::max(height / row_size, 1),
+        ));
     }
 
     let w_ratio = width * bound_height;
@@ -240,11 +510,6 @@ pub fn fit_in_bounds(
     }
 }
 
-/// Resize an image
-pub fn resize(image: &DynamicImage, width: u32, height: u32) -> DynamicImage {
-    image.resize_exact(width, height, image::imageops::Triangle)
-}
-
 /// Convert an image to a png buffer
 /// image is mainly supposed to be a GIF here
 pub fn convert_to_image_buffer(image: &DynamicImage, width: u32, height: u32) -> Result<Vec<u8>> {
@@ -258,9 +523,70 @@ pub fn convert_to_image_buffer(image: &DynamicImage, width: u32, height: u32) ->
     Ok(image_buffer)
 }
 
+/// Convert frames to an infinitely looping GIF buffer
+pub fn convert_to_gif_buffer(frames: &[(Duration, DynamicImage)]) -> Result<Vec<u8>> {
+    let mut gif_buffer = Vec::new();
+    {
+        let mut encoder = GifEncoder::new_with_speed(&mut gif_buffer, 10);
+        encoder.set_repeat(Repeat::Infinite)?;
+        encoder.encode_frames(frames.iter().map(|(delay, image)| {
+            Frame::from_parts(
+                image.to_rgba8(),
+                0,
+                0,
+                Delay::from_saturating_duration(*delay),
+            )
+        }))?;
+    }
+    Ok(gif_buffer)
+}
+
 /// Assess the transparency of a pixel
-pub fn pixel_is_transparent(rgb: [u8; 4]) -> bool {
-    rgb[3] < 25
+pub fn pixel_is_transparent(rgb: [u8; 4], threshold: u8) -> bool {
+    rgb[3] < threshold
+}
+
+const CHECKERBOARD_LIGHT: [u8; 3] = [204, 204, 204];
+const CHECKERBOARD_DARK: [u8; 3] = [153, 153, 153];
+
+/// Composite a pixel over the background, `square` is the checkerboard square size
+/// Pixels under the alpha threshold stay transparent unless using a checkerboard
+pub fn composite_pixel(
+    rgb: [u8; 4],
+    background: Background,
+    threshold: u8,
+    (x, y): (u32, u32),
+    square: u32,
+) -> [u8; 4] {
+    let bg = match background {
+        Background::Auto | Background::None => return rgb,
+        Background::Color([r, g, b]) if pixel_is_transparent(rgb, threshold) => {
+            return [r, g, b, 0]
+        }
+        Background::Color(color) => color,
+        Background::Checkerboard if (x / square + y / square) % 2 == 1 => CHECKERBOARD_DARK,
+        Background::Checkerboard => CHECKERBOARD_LIGHT,
+    };
+
+    let alpha = u32::from(rgb[3]);
+    let blend =
+        |fg: u8, bg: u8| ((u32::from(fg) * alpha + u32::from(bg) * (255 - alpha)) / 255) as u8;
+    [
+        blend(rgb[0], bg[0]),
+        blend(rgb[1], bg[1]),
+        blend(rgb[2], bg[2]),
+        255,
+    ]
+}
+
+/// Composite an image over the background
+pub fn composite(image: &DynamicImage, background: Background, threshold: u8) -> RgbaImage {
+    let mut rgba = image.to_rgba8();
+    let square = std::cmp::max(rgba.width().max(rgba.height()) / 32, 1);
+    for (x, y, pixel) in rgba.enumerate_pixels_mut() {
+        pixel.0 = composite_pixel(pixel.0, background, threshold, (x, y), square);
+    }
+    rgba
 }
 
 /// Convert rgb to ansi_rgb
@@ -282,11 +608,10 @@ pub fn ansi_indexed(rgb: [u8; 4], bg: bool) -> String {
     }
 }
 
-/// Convert rgb to ansi
-pub fn ansi_color(rgb: [u8; 4], bg: bool) -> String {
-    if support::truecolor() {
-        ansi_rgb(rgb, bg)
-    } else {
-        ansi_indexed(rgb, bg)
+/// Convert rgb to ansi with the given colour depth
+pub fn ansi_color(rgb: [u8; 4], bg: bool, colors: ColorMode) -> String {
+    match colors {
+        ColorMode::Truecolor => ansi_rgb(rgb, bg),
+        ColorMode::Ansi256 => ansi_indexed(rgb, bg),
     }
 }